@@ -0,0 +1,135 @@
+-- Replaces pg_bigm with pg_bigmr in the current database, rebuilding the
+-- indexes using the operator classes of pg_bigm with the ones of pg_bigmr of
+-- the same names, e.g. gin_bigm_ops. Run it with psql as the owner of pg_bigm
+-- and the indexes:
+--
+--   $ psql -d mydb -f migrate_from_pg_bigm.sql
+--
+-- Everything is done in one transaction, which fails if other objects like
+-- views still depend on pg_bigm. Set pg_bigmr.pg_bigm_compatibility to keep
+-- using the pg_bigm.* parameters.
+--
+-- With -v dry_run=on, the statements are only shown and nothing is changed.
+-- The regression test also sets from_extension to pg_bigmr, so that the
+-- statements are made from the indexes of pg_bigmr instead of pg_bigm.
+
+\set ON_ERROR_STOP on
+\if :{?dry_run}
+\else
+\set dry_run off
+\endif
+\if :{?from_extension}
+\else
+\set from_extension pg_bigm
+\endif
+
+BEGIN;
+
+SELECT pg_catalog.set_config('migrate_from_pg_bigm.extension', :'from_extension', true)
+        AS from_extension \gset
+
+DO $$
+BEGIN
+        IF NOT EXISTS (SELECT 1 FROM pg_catalog.pg_extension
+                       WHERE extname = current_setting('migrate_from_pg_bigm.extension')) THEN
+                RAISE EXCEPTION '% is not installed in database "%"',
+                        current_setting('migrate_from_pg_bigm.extension'), current_database();
+        END IF;
+END;
+$$;
+
+SELECT n.nspname AS pg_bigm_schema
+FROM pg_catalog.pg_extension e JOIN pg_catalog.pg_namespace n ON n.oid = e.extnamespace
+WHERE e.extname = :'from_extension' \gset
+
+-- The indexes of the partitions are left out when their partitioned index is
+-- migrated, since they are dropped and created together with it. As
+-- pg_get_indexdef() neither shows the tablespace nor creates the partitioned
+-- index on the partitions (ON ONLY), both are fixed up here.
+CREATE TEMP TABLE pg_bigm_indexes ON COMMIT DROP AS
+WITH indexes AS (
+        SELECT i.*
+        FROM pg_catalog.pg_index i
+        WHERE EXISTS (SELECT 1 FROM pg_catalog.pg_opclass oc
+                        JOIN pg_catalog.pg_depend d ON d.classid = 'pg_catalog.pg_opclass'::regclass
+                                AND d.objid = oc.oid AND d.deptype = 'e'
+                        JOIN pg_catalog.pg_extension e ON e.oid = d.refobjid
+                      WHERE oc.oid = ANY(i.indclass) AND e.extname = :'from_extension')
+), defs AS (
+        SELECT format('%I.%I', n.nspname, c.relname) AS index,
+                format('CREATE %sINDEX %s ON ', CASE WHEN x.indisunique THEN 'UNIQUE ' END,
+                        quote_ident(c.relname)) AS head,
+                pg_catalog.pg_get_indexdef(x.indexrelid) AS indexdef,
+                coalesce(' WHERE ' || pg_catalog.pg_get_expr(x.indpred, x.indrelid), '') AS predicate,
+                coalesce(' TABLESPACE ' || quote_ident(t.spcname), '') AS tablespace,
+                pg_catalog.obj_description(x.indexrelid, 'pg_class') AS description
+        FROM indexes x
+                JOIN pg_catalog.pg_class c ON c.oid = x.indexrelid
+                JOIN pg_catalog.pg_namespace n ON n.oid = c.relnamespace
+                LEFT JOIN pg_catalog.pg_tablespace t ON t.oid = c.reltablespace
+        WHERE NOT EXISTS (SELECT 1 FROM pg_catalog.pg_inherits h
+                          WHERE h.inhrelid = x.indexrelid
+                            AND h.inhparent IN (SELECT indexrelid FROM indexes))
+)
+SELECT index,
+        format('DROP INDEX %s', index) AS drop_statement,
+        -- the tablespace goes before the WHERE clause of a partial index
+        left(d.indexdef, length(d.indexdef) - length(predicate)) || tablespace || predicate
+                AS create_statement,
+        format('COMMENT ON INDEX %s IS ', index) || quote_literal(description) AS comment_statement
+FROM defs,
+        LATERAL (SELECT CASE WHEN starts_with(indexdef, head || 'ONLY ')
+                        THEN head || substr(indexdef, length(head || 'ONLY ') + 1)
+                        ELSE indexdef END AS indexdef) d;
+
+\if :dry_run
+
+SELECT statement AS "statements to run"
+FROM (SELECT 1 AS step, index, drop_statement AS statement FROM pg_bigm_indexes
+      UNION ALL
+      SELECT 2, NULL, format('DROP EXTENSION %I', :'from_extension')
+      UNION ALL
+      SELECT 3, NULL, format('CREATE EXTENSION pg_bigmr SCHEMA %I', :'pg_bigm_schema')
+      UNION ALL
+      SELECT 4, index, create_statement FROM pg_bigm_indexes
+      UNION ALL
+      SELECT 5, index, comment_statement FROM pg_bigm_indexes) s
+WHERE statement IS NOT NULL
+ORDER BY step, index;
+
+ROLLBACK;
+
+\else
+
+SELECT index AS "indexes to migrate" FROM pg_bigm_indexes ORDER BY index;
+
+DO $$
+DECLARE
+        r       record;
+BEGIN
+        FOR r IN SELECT * FROM pg_bigm_indexes LOOP
+                EXECUTE r.drop_statement;
+        END LOOP;
+END;
+$$;
+
+-- fails if anything else depends on pg_bigm
+DROP EXTENSION pg_bigm;
+CREATE EXTENSION pg_bigmr SCHEMA :"pg_bigm_schema";
+
+DO $$
+DECLARE
+        r       record;
+BEGIN
+        FOR r IN SELECT * FROM pg_bigm_indexes LOOP
+                EXECUTE r.create_statement;
+                IF r.comment_statement IS NOT NULL THEN
+                        EXECUTE r.comment_statement;
+                END IF;
+        END LOOP;
+END;
+$$;
+
+COMMIT;
+
+\endif
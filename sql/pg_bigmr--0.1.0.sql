@@ -1,18 +1,262 @@
 -- complain if script is sourced in psql, rather than via CREATE EXTENSION
 \echo Use "CREATE EXTENSION pg_bigmr" to load this file. \quit
 
+CREATE OPERATOR =% (
+        LEFTARG = text,
+        RIGHTARG = bigm_similar_query,
+        PROCEDURE = bigm_similar_query_op,
+        RESTRICT = bigm_sel,
+        JOIN = contjoinsel
+);
+
 -- create the operator class for gin
 CREATE OPERATOR CLASS gin_bigm_ops
 FOR TYPE text USING gin
 AS
         OPERATOR        1       pg_catalog.~~ (text, text),
         OPERATOR        2       =% (text, text),
+        OPERATOR        5       pg_catalog.~ (text, text),
+        OPERATOR        7       %> (text, text),
+        OPERATOR        8       %>> (text, text),
+        OPERATOR        9       =% (text, bigm_similar_query),
+        OPERATOR        10      @@ (text, bigm_query),
+        OPERATOR        11      ~~| (text, text[]),
         FUNCTION        1       bigmtextcmp (text, text),
         FUNCTION        2       gin_extract_value_bigm (text, internal),
-        FUNCTION        3       gin_extract_query_bigm (text, internal, int2, internal, internal, internal, internal),
-        FUNCTION        4       gin_bigm_consistent (internal, int2, text, int4, internal, internal, internal, internal),
+        FUNCTION        3       gin_extract_query_bigm (internal, internal, int2, internal, internal, internal, internal),
+        FUNCTION        4       gin_bigm_consistent (internal, int2, internal, int4, internal, internal, internal, internal),
         FUNCTION        5       gin_bigm_compare_partial (text, text, int2, internal),
         STORAGE         text;
 
 ALTER OPERATOR FAMILY gin_bigm_ops USING gin ADD
-        FUNCTION        6    (text, text) gin_bigm_triconsistent (internal, int2, text, int4, internal, internal, internal);
+        FUNCTION        6    (text, text) gin_bigm_triconsistent (internal, int2, internal, int4, internal, internal, internal);
+
+-- create the operator class for gin storing the n-grams as packed int8 keys,
+-- which makes the index smaller and its key comparisons faster
+CREATE OPERATOR CLASS gin_bigm_int_ops
+FOR TYPE text USING gin
+AS
+        OPERATOR        1       pg_catalog.~~ (text, text),
+        OPERATOR        2       =% (text, text),
+        OPERATOR        5       pg_catalog.~ (text, text),
+        OPERATOR        7       %> (text, text),
+        OPERATOR        8       %>> (text, text),
+        OPERATOR        9       =% (text, bigm_similar_query),
+        OPERATOR        10      @@ (text, bigm_query),
+        OPERATOR        11      ~~| (text, text[]),
+        FUNCTION        1       pg_catalog.btint8cmp (int8, int8),
+        FUNCTION        2       gin_extract_value_bigm_int (text, internal),
+        FUNCTION        3       gin_extract_query_bigm_int (internal, internal, int2, internal, internal, internal, internal),
+        FUNCTION        4       gin_bigm_consistent (internal, int2, internal, int4, internal, internal, internal, internal),
+        FUNCTION        5       gin_bigm_compare_partial_int (int8, int8, int2, internal),
+        STORAGE         int8;
+
+ALTER OPERATOR FAMILY gin_bigm_int_ops USING gin ADD
+        FUNCTION        6    (text, text) gin_bigm_triconsistent (internal, int2, internal, int4, internal, internal, internal);
+
+-- create the operator classes for gin storing the case-folded bigrams as well,
+-- which ILIKE and ~* search for. The other operator classes leave out these
+-- operators, since they would have to scan the whole index.
+CREATE OPERATOR CLASS gin_bigm_ilike_ops
+FOR TYPE text USING gin
+AS
+        OPERATOR        1       pg_catalog.~~ (text, text),
+        OPERATOR        2       =% (text, text),
+        OPERATOR        4       pg_catalog.~~* (text, text),
+        OPERATOR        5       pg_catalog.~ (text, text),
+        OPERATOR        6       pg_catalog.~* (text, text),
+        OPERATOR        7       %> (text, text),
+        OPERATOR        8       %>> (text, text),
+        OPERATOR        9       =% (text, bigm_similar_query),
+        OPERATOR        10      @@ (text, bigm_query),
+        OPERATOR        11      ~~| (text, text[]),
+        FUNCTION        1       bigmtextcmp (text, text),
+        FUNCTION        2       gin_extract_value_bigm_ilike (text, internal),
+        FUNCTION        3       gin_extract_query_bigm (internal, internal, int2, internal, internal, internal, internal),
+        FUNCTION        4       gin_bigm_consistent (internal, int2, internal, int4, internal, internal, internal, internal),
+        FUNCTION        5       gin_bigm_compare_partial (text, text, int2, internal),
+        STORAGE         text;
+
+ALTER OPERATOR FAMILY gin_bigm_ilike_ops USING gin ADD
+        FUNCTION        6    (text, text) gin_bigm_triconsistent (internal, int2, internal, int4, internal, internal, internal);
+
+CREATE OPERATOR CLASS gin_bigm_int_ilike_ops
+FOR TYPE text USING gin
+AS
+        OPERATOR        1       pg_catalog.~~ (text, text),
+        OPERATOR        2       =% (text, text),
+        OPERATOR        4       pg_catalog.~~* (text, text),
+        OPERATOR        5       pg_catalog.~ (text, text),
+        OPERATOR        6       pg_catalog.~* (text, text),
+        OPERATOR        7       %> (text, text),
+        OPERATOR        8       %>> (text, text),
+        OPERATOR        9       =% (text, bigm_similar_query),
+        OPERATOR        10      @@ (text, bigm_query),
+        OPERATOR        11      ~~| (text, text[]),
+        FUNCTION        1       pg_catalog.btint8cmp (int8, int8),
+        FUNCTION        2       gin_extract_value_bigm_int_ilike (text, internal),
+        FUNCTION        3       gin_extract_query_bigm_int (internal, internal, int2, internal, internal, internal, internal),
+        FUNCTION        4       gin_bigm_consistent (internal, int2, internal, int4, internal, internal, internal, internal),
+        FUNCTION        5       gin_bigm_compare_partial_int (int8, int8, int2, internal),
+        STORAGE         int8;
+
+ALTER OPERATOR FAMILY gin_bigm_int_ilike_ops USING gin ADD
+        FUNCTION        6    (text, text) gin_bigm_triconsistent (internal, int2, internal, int4, internal, internal, internal);
+
+-- opclass options are supported in PostgreSQL 13 or later
+DO $$
+BEGIN
+        IF current_setting('server_version_num')::int >= 130000 THEN
+                ALTER OPERATOR FAMILY gin_bigm_ops USING gin ADD
+                        FUNCTION        7    (text) gin_bigm_options (internal);
+                ALTER OPERATOR FAMILY gin_bigm_int_ops USING gin ADD
+                        FUNCTION        7    (text) gin_bigm_options (internal);
+                ALTER OPERATOR FAMILY gin_bigm_ilike_ops USING gin ADD
+                        FUNCTION        7    (text) gin_bigm_options (internal);
+                ALTER OPERATOR FAMILY gin_bigm_int_ilike_ops USING gin ADD
+                        FUNCTION        7    (text) gin_bigm_options (internal);
+        END IF;
+END
+$$;
+
+-- create the operator class for gin on bigm
+CREATE OPERATOR CLASS bigm_ops
+DEFAULT FOR TYPE bigm USING gin
+AS
+        OPERATOR        2       =% (bigm, bigm),
+        FUNCTION        1       pg_catalog.btint8cmp (int8, int8),
+        FUNCTION        2       gin_extract_value_bigm_vector (bigm, internal),
+        FUNCTION        3       gin_extract_query_bigm_vector (bigm, internal, int2, internal, internal, internal, internal),
+        FUNCTION        4       gin_bigm_consistent (internal, int2, internal, int4, internal, internal, internal, internal),
+        STORAGE         int8;
+
+ALTER OPERATOR FAMILY bigm_ops USING gin ADD
+        FUNCTION        6    (bigm, bigm) gin_bigm_triconsistent (internal, int2, internal, int4, internal, internal, internal);
+
+-- most common bigrams of the bigm columns and the index expressions like
+-- to_bigm(col) collected by ANALYZE, where the ones of an index expression are
+-- shown with the table of the index, the expression and the index. Text
+-- columns only have them through such an index, since ANALYZE collects the
+-- statistics of text columns and expressions in the standard way.
+CREATE VIEW pg_bigm_stats AS
+SELECT n.nspname AS schemaname, t.relname AS tablename,
+        CASE WHEN i.indexrelid IS NULL THEN a.attname::text
+             ELSE pg_catalog.pg_get_indexdef(c.oid, a.attnum, true) END AS attname,
+        CASE WHEN i.indexrelid IS NOT NULL THEN c.relname END AS indexname,
+        m.bigram, m.frequency
+FROM pg_catalog.pg_stats s
+        JOIN pg_catalog.pg_namespace n ON n.nspname = s.schemaname
+        JOIN pg_catalog.pg_class c ON c.relnamespace = n.oid AND c.relname = s.tablename
+        JOIN pg_catalog.pg_attribute a ON a.attrelid = c.oid AND a.attname = s.attname
+        LEFT JOIN pg_catalog.pg_index i ON i.indexrelid = c.oid
+        JOIN pg_catalog.pg_class t ON t.oid = coalesce(i.indrelid, c.oid),
+        LATERAL unnest(s.most_common_elems::text::text[], s.most_common_elem_freqs)
+                AS m(bigram, frequency)
+WHERE m.bigram IS NOT NULL
+        AND (a.atttypid = 'bigm'::regtype OR
+             -- index columns have the storage type of their operator class
+             EXISTS (SELECT 1 FROM pg_catalog.pg_opclass o
+                     WHERE o.oid = i.indclass[a.attnum - 1] AND o.opcintype = 'bigm'::regtype));
+
+-- pending lists of the GIN indexes using the operator classes of pg_bigmr in
+-- the current database, where gin_pending_list_limit is in kB and falls back
+-- to the setting of this session. The temporary indexes of other sessions are
+-- skipped since their pages cannot be read.
+CREATE VIEW pg_bigm_pending_stats AS
+SELECT x.schemaname, x.tablename, x.indexname, p.pages, p.tuples,
+        coalesce(o.fastupdate, true) AS fastupdate,
+        coalesce(o.gin_pending_list_limit,
+                 (SELECT setting::int FROM pg_catalog.pg_settings
+                  WHERE name = 'gin_pending_list_limit')) AS gin_pending_list_limit
+FROM (SELECT n.nspname AS schemaname, t.relname AS tablename, c.relname AS indexname,
+                c.oid AS indexoid, c.reloptions
+        FROM pg_catalog.pg_index i
+                JOIN pg_catalog.pg_class c ON c.oid = i.indexrelid
+                JOIN pg_catalog.pg_class t ON t.oid = i.indrelid
+                JOIN pg_catalog.pg_namespace n ON n.oid = c.relnamespace
+        WHERE c.relkind = 'i' AND i.indisready
+                AND (c.relpersistence <> 't' OR c.relnamespace = pg_catalog.pg_my_temp_schema())
+                AND EXISTS (SELECT 1 FROM pg_catalog.pg_opclass oc
+                                JOIN pg_catalog.pg_am am ON am.oid = oc.opcmethod
+                                JOIN pg_catalog.pg_depend d
+                                        ON d.classid = 'pg_catalog.pg_opclass'::regclass
+                                        AND d.objid = oc.oid AND d.deptype = 'e'
+                                JOIN pg_catalog.pg_extension e ON e.oid = d.refobjid
+                            WHERE oc.oid = ANY(i.indclass) AND am.amname = 'gin'
+                                AND e.extname = 'pg_bigmr')
+        -- keep the filters above from being applied after reading the pages
+        OFFSET 0) AS x,
+        LATERAL (SELECT max(option_value) FILTER (WHERE option_name = 'fastupdate')::bool,
+                        max(option_value) FILTER (WHERE option_name = 'gin_pending_list_limit')::int
+                 FROM pg_catalog.pg_options_to_table(x.reloptions))
+                AS o(fastupdate, gin_pending_list_limit),
+        LATERAL pg_gin_pending_stats(x.indexoid) AS p;
+
+-- similarity search with its own threshold, which can use the index since
+-- this function is inlined into the =% operator with bigm_similar_query
+CREATE FUNCTION bigm_similar(text, text, float4)
+RETURNS bool
+AS 'SELECT $1 =% ROW($2, $3)::bigm_similar_query'
+LANGUAGE sql STABLE PARALLEL SAFE;
+
+-- create the operator class for gist
+CREATE OPERATOR CLASS gist_bigm_ops
+FOR TYPE text USING gist
+AS
+        OPERATOR        1       pg_catalog.~~ (text, text),
+        OPERATOR        2       =% (text, text),
+        OPERATOR        3       <-> (text, text) FOR ORDER BY pg_catalog.float_ops,
+        FUNCTION        1       gist_bigm_consistent (internal, text, int2, oid, internal),
+        FUNCTION        2       gist_bigm_union (internal, internal),
+        FUNCTION        3       gist_bigm_compress (internal),
+        FUNCTION        4       gist_bigm_decompress (internal),
+        FUNCTION        5       gist_bigm_penalty (internal, internal, internal),
+        FUNCTION        6       gist_bigm_picksplit (internal, internal),
+        FUNCTION        7       gist_bigm_same (bytea, bytea, internal),
+        FUNCTION        8       gist_bigm_distance (internal, text, int2, oid, internal),
+        STORAGE         bytea;
+
+-- frequencies of the bigrams used by pg_bigmr.gin_key_selection = rarest,
+-- i.e. the number of the rows including each bigram in each column of the
+-- indexes when pg_bigm_collect_key_stats() was called, which has to be called
+-- again after the data changes since nothing else refreshes them. The keys are
+-- in the C collation to be looked up directly.
+CREATE TABLE pg_bigm_key_stats (
+        key             text COLLATE "C" NOT NULL,
+        indexrelid      oid NOT NULL,
+        attnum          int2 NOT NULL,
+        frequency       int8 NOT NULL,
+        PRIMARY KEY (key, indexrelid, attnum)
+);
+SELECT pg_catalog.pg_extension_config_dump('pg_bigm_key_stats', '');
+
+-- replace the frequencies of the index with the numbers of the rows in the
+-- posting lists of its keys, and return the number of the keys. The ones of
+-- the dropped indexes are removed as well.
+CREATE FUNCTION pg_bigm_collect_key_stats(index regclass)
+RETURNS int8
+AS $$
+DECLARE
+        nsp     text;
+        nkeys   int8;
+BEGIN
+        SELECT extnamespace::regnamespace INTO nsp
+        FROM pg_catalog.pg_extension WHERE extname = 'pg_bigmr';
+        EXECUTE format(
+                'DELETE FROM %s.pg_bigm_key_stats s WHERE s.indexrelid = $1 OR NOT EXISTS '
+                '(SELECT FROM pg_catalog.pg_class c WHERE c.oid = s.indexrelid)',
+                nsp) USING index;
+        EXECUTE format(
+                'INSERT INTO %1$s.pg_bigm_key_stats '
+                'SELECT bigram, $1, attnum, postings '
+                'FROM %1$s.pg_bigm_index_stats($1) WHERE NOT casefold',
+                nsp) USING index;
+        GET DIAGNOSTICS nkeys = ROW_COUNT;
+        RETURN nkeys;
+END;
+$$
+LANGUAGE plpgsql VOLATILE;
+
+-- the keys of an index tell the contents of its column, so they are only shown
+-- to the users granted EXECUTE who can read every row of the table as well
+REVOKE EXECUTE ON FUNCTION pg_bigm_index_stats(oid, int4) FROM PUBLIC;
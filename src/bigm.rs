@@ -0,0 +1,426 @@
+use std::{
+    cmp,
+    collections::HashMap,
+    ffi::{c_int, CStr, CString},
+    mem,
+};
+
+use pgrx::{
+    callconv::{Arg, ArgAbi, BoxRet, FcInfo},
+    pg_sys,
+    pgrx_sql_entity_graph::metadata::{
+        ArgumentError, Returns, ReturnsError, SqlMapping, SqlTranslatable,
+    },
+    prelude::*,
+    varlena, Internal, PgMemoryContexts,
+};
+
+use crate::{bigram::BigramList, gucs, show_bigm, similarity_score, SIMILARITY_STRATEGY_NUMBER};
+
+// The bigm type is created in two steps, since its input and output functions
+// need the shell type.
+extension_sql!(
+    r#"
+-- precomputed set of the bigrams of a text
+CREATE TYPE bigm;
+"#,
+    name = "bigm_shell",
+);
+extension_sql!(
+    r#"
+CREATE TYPE bigm (
+        INTERNALLENGTH = variable,
+        INPUT = bigm_in,
+        OUTPUT = bigm_out,
+        RECEIVE = bigm_recv,
+        SEND = bigm_send,
+        ANALYZE = bigm_typanalyze,
+        STORAGE = extended
+);
+"#,
+    name = "bigm",
+    requires = [bigm_in, bigm_out, bigm_recv, bigm_send, bigm_typanalyze],
+);
+
+// Value of the bigm type, i.e. the sorted and deduplicated bigrams of a text
+// like show_bigm() returns. Each bigram is held as its ngram_code(), which is
+// ordered as the bigrams are, so that two values can be compared by merging
+// them without any allocation.
+#[derive(Debug, Default)]
+pub struct BigmVector {
+    codes: Vec<i64>,
+}
+
+impl BigmVector {
+    fn from_bigrams<S: AsRef<str>>(bigrams: &[S]) -> Self {
+        let mut codes: Vec<i64> = bigrams
+            .iter()
+            .map(|bgm| BigramList::ngram_code(bgm.as_ref()))
+            .collect();
+        codes.sort_unstable();
+        codes.dedup();
+        Self { codes }
+    }
+
+    pub fn bigrams(&self) -> impl Iterator<Item = String> + '_ {
+        self.codes
+            .iter()
+            .map(|code| BigramList::ngram_of_code(*code).unwrap_or_default())
+    }
+
+    // Returns the number of the bigrams included in both values.
+    fn count_common(&self, other: &Self) -> usize {
+        let (mut i, mut j, mut count) = (0, 0, 0);
+        while i < self.codes.len() && j < other.codes.len() {
+            match self.codes[i].cmp(&other.codes[j]) {
+                cmp::Ordering::Less => i += 1,
+                cmp::Ordering::Greater => j += 1,
+                cmp::Ordering::Equal => {
+                    i += 1;
+                    j += 1;
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+}
+
+// The value is stored as a varlena of the native-endian codes.
+impl FromDatum for BigmVector {
+    unsafe fn from_polymorphic_datum(
+        datum: pg_sys::Datum,
+        is_null: bool,
+        _typoid: pg_sys::Oid,
+    ) -> Option<Self> {
+        let bytes = unsafe { <&[u8]>::from_datum(datum, is_null)? };
+        let codes = bytes
+            .chunks_exact(mem::size_of::<i64>())
+            .map(|chunk| i64::from_ne_bytes(chunk.try_into().unwrap()))
+            .collect();
+        Some(Self { codes })
+    }
+}
+
+impl IntoDatum for BigmVector {
+    fn into_datum(self) -> Option<pg_sys::Datum> {
+        let bytes: Vec<u8> = self
+            .codes
+            .iter()
+            .flat_map(|code| code.to_ne_bytes())
+            .collect();
+        bytes.as_slice().into_datum()
+    }
+
+    fn type_oid() -> pg_sys::Oid {
+        pgrx::wrappers::regtypein("bigm")
+    }
+}
+
+unsafe impl<'fcx> ArgAbi<'fcx> for BigmVector {
+    unsafe fn unbox_arg_unchecked(arg: Arg<'_, 'fcx>) -> Self {
+        let index = arg.index();
+        unsafe {
+            arg.unbox_arg_using_from_datum()
+                .unwrap_or_else(|| panic!("argument {index} must not be null"))
+        }
+    }
+}
+
+unsafe impl BoxRet for BigmVector {
+    unsafe fn box_into<'fcx>(self, fcinfo: &mut FcInfo<'fcx>) -> pgrx::datum::Datum<'fcx> {
+        match self.into_datum() {
+            Some(datum) => unsafe { fcinfo.return_raw_datum(datum) },
+            None => fcinfo.return_null(),
+        }
+    }
+}
+
+unsafe impl SqlTranslatable for BigmVector {
+    fn argument_sql() -> Result<SqlMapping, ArgumentError> {
+        Ok(SqlMapping::As(String::from("bigm")))
+    }
+
+    fn return_sql() -> Result<Returns, ReturnsError> {
+        Ok(Returns::One(SqlMapping::As(String::from("bigm"))))
+    }
+}
+
+// The text representation is the list of the quoted bigrams like tsvector,
+// e.g. ' p' 'pg' 'g '. A quote in a bigram is doubled.
+#[pg_extern(immutable, parallel_safe, strict, requires = ["bigm_shell"])]
+fn bigm_in(input: &CStr) -> BigmVector {
+    let input = input.to_string_lossy();
+    let bigrams = parse_bigrams(&input)
+        .unwrap_or_else(|| pgrx::error!("invalid input syntax for type bigm: \"{input}\""));
+    BigmVector::from_bigrams(&bigrams)
+}
+
+fn parse_bigrams(input: &str) -> Option<Vec<String>> {
+    let mut bigrams = Vec::new();
+    let mut chars = input.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        match chars.next() {
+            None => break,
+            Some('\'') => {}
+            Some(_) => return None,
+        }
+        let mut bigram = String::new();
+        loop {
+            match chars.next()? {
+                '\'' if chars.next_if_eq(&'\'').is_none() => break,
+                '\0' => return None,
+                c => bigram.push(c),
+            }
+        }
+        if bigram.chars().count() != 2 {
+            return None;
+        }
+        bigrams.push(bigram);
+    }
+    Some(bigrams)
+}
+
+#[pg_extern(immutable, parallel_safe, strict, requires = ["bigm_shell"])]
+fn bigm_out(input: BigmVector) -> CString {
+    let output = input
+        .bigrams()
+        .map(|bgm| format!("'{}'", bgm.replace('\'', "''")))
+        .collect::<Vec<_>>()
+        .join(" ");
+    CString::new(output).unwrap()
+}
+
+// The binary representation is the number of the bigrams followed by the
+// code points of both characters of each bigram.
+#[pg_extern(immutable, parallel_safe, strict, requires = ["bigm_shell"])]
+fn bigm_recv(buf: Internal) -> BigmVector {
+    let buf = buf
+        .unwrap()
+        .unwrap()
+        .cast_mut_ptr::<pg_sys::StringInfoData>();
+    let nbigrams = unsafe { pg_sys::pq_getmsgint(buf, 4) } as i32;
+    if nbigrams < 0 {
+        pgrx::error!("invalid number of bigrams in external bigm value");
+    }
+    let bigrams: Vec<String> = (0..nbigrams)
+        .map(|_| {
+            (0..2)
+                .map(|_| {
+                    let code = unsafe { pg_sys::pq_getmsgint(buf, 4) };
+                    char::from_u32(code)
+                        .filter(|c| *c != '\0')
+                        .unwrap_or_else(|| pgrx::error!("invalid bigram in external bigm value"))
+                })
+                .collect()
+        })
+        .collect();
+    BigmVector::from_bigrams(&bigrams)
+}
+
+#[pg_extern(immutable, parallel_safe, strict, requires = ["bigm_shell"])]
+fn bigm_send(input: BigmVector) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(4 + input.codes.len() * 8);
+    bytes.extend((input.codes.len() as u32).to_be_bytes());
+    for bgm in input.bigrams() {
+        for c in bgm.chars() {
+            bytes.extend(u32::from(c).to_be_bytes());
+        }
+    }
+    bytes
+}
+
+#[pg_extern(immutable, parallel_safe, strict, requires = ["bigm"])]
+fn to_bigm(input: &str) -> BigmVector {
+    BigmVector::from_bigrams(&show_bigm(input))
+}
+
+#[pg_extern(parallel_safe, stable, strict, name = "bigm_similarity", requires = ["bigm"])]
+fn bigm_vector_similarity(input1: BigmVector, input2: BigmVector) -> f32 {
+    let count = input1.count_common(&input2);
+    similarity_score(count, input1.codes.len(), input2.codes.len())
+}
+
+#[pg_operator(parallel_safe, stable, strict, requires = [bigm_sel, "bigm"])]
+#[opname(=%)]
+#[commutator(=%)]
+#[restrict(bigm_sel)]
+#[join(contjoinsel)]
+fn bigm_vector_similarity_op(input1: BigmVector, input2: BigmVector) -> bool {
+    let similarity = bigm_vector_similarity(input1, input2);
+    similarity >= gucs::similarity_limit() as f32
+}
+
+#[pg_operator(parallel_safe, stable, strict, requires = ["bigm"])]
+#[opname(<->)]
+#[commutator(<->)]
+fn bigm_vector_distance(input1: BigmVector, input2: BigmVector) -> f32 {
+    1.0 - bigm_vector_similarity(input1, input2)
+}
+
+// Statistics of bigm columns, including the expressions of indexes like
+// to_bigm(col), are the most common bigrams and the fraction of the non-null
+// rows including each of them. They are stored as the most common elements
+// like the ones of tsvector, so that they are shown in pg_stats. Text columns
+// can't have them, since ANALYZE uses the typanalyze function of the type of
+// the column or the index expression.
+#[pg_extern(requires = ["bigm_shell"])]
+fn bigm_typanalyze(stats: Internal) -> bool {
+    let stats = stats
+        .unwrap()
+        .unwrap()
+        .cast_mut_ptr::<pg_sys::VacAttrStats>();
+    unsafe {
+        (*stats).compute_stats = Some(compute_bigm_stats);
+        // Same as std_typanalyze()
+        (*stats).minrows = 300 * statistics_target(stats);
+    }
+    true
+}
+
+// The statistics target is attstattarget of pg_attribute, where -1 means
+// default_statistics_target, before PostgreSQL 17, which makes it a nullable
+// int2 and gives VacAttrStats the target with the default applied instead.
+#[cfg(not(feature = "pg17"))]
+unsafe fn statistics_target(stats: *mut pg_sys::VacAttrStats) -> i32 {
+    match (*(*stats).attr).attstattarget {
+        target if target < 0 => pg_sys::default_statistics_target,
+        target => target,
+    }
+}
+
+#[cfg(feature = "pg17")]
+unsafe fn statistics_target(stats: *mut pg_sys::VacAttrStats) -> i32 {
+    (*stats).attstattarget
+}
+
+#[pg_guard]
+unsafe extern "C" fn compute_bigm_stats(
+    stats: pg_sys::VacAttrStatsP,
+    fetchfunc: pg_sys::AnalyzeAttrFetchFunc,
+    samplerows: c_int,
+    _totalrows: f64,
+) {
+    let fetchfunc = fetchfunc.unwrap();
+    let mut null_cnt = 0;
+    let mut total_width = 0;
+    // Number of the sampled rows including each bigram
+    let mut counts: HashMap<i64, i32> = HashMap::new();
+    for i in 0..samplerows {
+        pg_sys::vacuum_delay_point();
+
+        let mut isnull = false;
+        let value = fetchfunc(stats, i, &mut isnull);
+        if isnull {
+            null_cnt += 1;
+            continue;
+        }
+        total_width += varlena::varsize_any(value.cast_mut_ptr());
+        let vector = BigmVector::from_datum(value, false).unwrap();
+        for code in vector.codes {
+            *counts.entry(code).or_default() += 1;
+        }
+    }
+
+    let stats = &mut *stats;
+    stats.stats_valid = true;
+    let nonnull_cnt = samplerows - null_cnt;
+    if nonnull_cnt == 0 {
+        // All the sampled values are NULL.
+        stats.stanullfrac = 1.0;
+        stats.stawidth = 0;
+        stats.stadistinct = 0.0;
+        return;
+    }
+    stats.stanullfrac = null_cnt as f32 / samplerows as f32;
+    stats.stawidth = (total_width / nonnull_cnt as usize) as i32;
+    // Assume the values are unique as tsvector does.
+    stats.stadistinct = -(1.0 - stats.stanullfrac);
+
+    // Keep as many bigrams as tsvector keeps lexemes, ordered by the bigram.
+    let mut mcb: Vec<(i64, i32)> = counts.into_iter().collect();
+    mcb.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    mcb.truncate((statistics_target(stats) * 10) as usize);
+    mcb.sort_unstable();
+    if mcb.is_empty() {
+        return;
+    }
+
+    PgMemoryContexts::For(stats.anl_context).switch_to(|context| {
+        let values = context.palloc_slice::<pg_sys::Datum>(mcb.len());
+        // The frequencies are followed by the minimum and the maximum ones.
+        let numbers = context.palloc_slice::<f32>(mcb.len() + 2);
+        for (i, (code, count)) in mcb.iter().enumerate() {
+            let bigram = BigramList::ngram_of_code(*code).unwrap_or_default();
+            values[i] = bigram.into_datum().unwrap();
+            numbers[i] = *count as f32 / nonnull_cnt as f32;
+        }
+        let (min_count, max_count) = mcb.iter().fold((i32::MAX, 0), |(min, max), (_, count)| {
+            (min.min(*count), max.max(*count))
+        });
+        numbers[mcb.len()] = min_count as f32 / nonnull_cnt as f32;
+        numbers[mcb.len() + 1] = max_count as f32 / nonnull_cnt as f32;
+
+        stats.stakind[0] = pg_sys::STATISTIC_KIND_MCELEM as i16;
+        stats.staop[0] = pg_sys::Oid::from(pg_sys::TextEqualOperator);
+        stats.stacoll[0] = pg_sys::DEFAULT_COLLATION_OID;
+        stats.numnumbers[0] = numbers.len() as i32;
+        stats.stanumbers[0] = numbers.as_mut_ptr();
+        stats.numvalues[0] = values.len() as i32;
+        stats.stavalues[0] = values.as_mut_ptr();
+        stats.statypid[0] = pg_sys::TEXTOID;
+        stats.statyplen[0] = -1;
+        stats.statypbyval[0] = false;
+        stats.statypalign[0] = b'i' as _;
+    });
+}
+
+// Support functions of bigm_ops, the gin operator class for bigm. Their keys
+// are the codes of the bigrams, and gin_bigm_consistent() and
+// gin_bigm_triconsistent() are shared with gin_bigm_ops.
+#[pg_extern(immutable, parallel_safe, strict, requires = ["bigm"])]
+fn gin_extract_value_bigm_vector(item_value: BigmVector, nkeys: Internal) -> Internal {
+    code_entries(&item_value.codes, nkeys)
+}
+
+#[allow(clippy::too_many_arguments)]
+#[pg_extern(immutable, parallel_safe, strict, requires = ["bigm"])]
+fn gin_extract_query_bigm_vector(
+    query: BigmVector,
+    nkeys: Internal,
+    strategy_number: i16,
+    _pmatch: Internal,
+    _extra_data: Internal,
+    _null_flags: Internal,
+    search_mode: Internal,
+) -> Internal {
+    if strategy_number != SIMILARITY_STRATEGY_NUMBER {
+        pgrx::error!("unrecognized strategy number: {strategy_number}");
+    }
+
+    // As for text, let the consistent functions decide on the empty query.
+    if query.codes.is_empty() {
+        unsafe {
+            let mut search_mode_ptr = PgBox::from_pg(search_mode.get_mut().unwrap() as *mut u32);
+            *search_mode_ptr = pg_sys::GIN_SEARCH_MODE_ALL
+        }
+    }
+    code_entries(&query.codes, nkeys)
+}
+
+fn code_entries(codes: &[i64], nkeys: Internal) -> Internal {
+    unsafe {
+        let mut nkeys_ptr = PgBox::from_pg(nkeys.get_mut().unwrap() as *mut i32);
+        *nkeys_ptr = codes.len() as i32;
+    };
+
+    let entries = unsafe {
+        PgMemoryContexts::CurrentMemoryContext.palloc0_slice::<pg_sys::Datum>(codes.len())
+    };
+    for (entry, code) in entries.iter_mut().zip(codes) {
+        *entry = pg_sys::Datum::from(*code);
+    }
+
+    Internal::from(Some(pg_sys::Datum::from(entries.as_mut_ptr())))
+}
@@ -1,23 +1,82 @@
-#[derive(Debug)]
+use std::{borrow::Cow, cmp, iter, sync::OnceLock};
+
+use unicode_normalization::{
+    char::{canonical_combining_class, compose},
+    is_nfkc_quick, IsNormalized, UnicodeNormalization,
+};
+
+#[derive(Debug, Default)]
 pub struct BigramList {
     pub bigrams: Vec<String>,
     pub removed_dups: bool,
     pub pmatch: bool,
 }
 
+// Size of the n-grams extracted from texts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GramSize {
+    Fixed(usize),
+    // Trigrams for ASCII characters and bigrams for the others, since ASCII
+    // bigrams are too common to narrow down the search.
+    #[cfg_attr(feature = "pg12", allow(dead_code))]
+    MixedScript,
+}
+
+impl Default for GramSize {
+    fn default() -> Self {
+        Self::Fixed(2)
+    }
+}
+
 impl BigramList {
     pub fn from_value(value: &str) -> Self {
+        Self::from_value_ngrams(value, GramSize::default())
+    }
+
+    pub fn from_value_ngrams(value: &str, gram_size: GramSize) -> Self {
         let padded_value = format!(" {} ", value);
-        let bigrams = Self::make_bigrams(&padded_value);
+        let bigrams = Self::make_ngrams(&padded_value, gram_size, false);
         Self::remove_duplicate_bigms(bigrams, false)
     }
 
     pub fn from_query(query: &str) -> Self {
+        Self::from_query_normalized(query, &Normalizer::default(), GramSize::default())
+    }
+
+    // Normalizes each part of the query between wildcards, since normalizing
+    // the whole query could turn ordinary characters into wildcards.
+    pub fn from_query_normalized(
+        query: &str,
+        normalizer: &Normalizer,
+        gram_size: GramSize,
+    ) -> Self {
         let pmatch = query.chars().count() < 2;
-        let bigrams = Self::make_bigrams_of_query(query);
+        let bigrams = Self::make_bigrams_of_query(query, normalizer, gram_size);
         Self::remove_duplicate_bigms(bigrams, pmatch)
     }
 
+    // Returns the bigrams of the value in the order they appear, without
+    // removing duplicates.
+    pub fn ordered_from_value(value: &str) -> Vec<String> {
+        let padded_value = format!(" {} ", value);
+        Self::make_bigrams(&padded_value)
+    }
+
+    pub fn from_value_casefold(value: &str, gram_size: GramSize) -> Self {
+        Self::from_value_ngrams(&lowercase(value), gram_size)
+    }
+
+    // Lower-cases each part of the query after the normalization, as the
+    // indexed value is lower-cased after it.
+    pub fn from_query_casefold(query: &str, normalizer: &Normalizer, gram_size: GramSize) -> Self {
+        Self::from_query_normalized(query, &normalizer.clone().casefold(), gram_size)
+    }
+
+    // Returns the n-grams that every text including the literal must include.
+    pub fn from_literal(literal: &str, gram_size: GramSize) -> Vec<String> {
+        Self::make_ngrams(literal, gram_size, true)
+    }
+
     // Adds bigrams from words (already padded).
     fn make_bigrams(padded_str: &str) -> Vec<String> {
         let mut bigrams = Vec::new();
@@ -32,15 +91,69 @@ impl BigramList {
         bigrams
     }
 
-    fn make_bigrams_of_query(query: &str) -> Vec<String> {
+    // Adds n-grams from words. If open_end is true, the text may be followed
+    // by other characters, so n-grams which depend on them are skipped.
+    fn make_ngrams(text: &str, gram_size: GramSize, open_end: bool) -> Vec<String> {
+        let chars: Vec<char> = text.chars().collect();
+        match gram_size {
+            GramSize::Fixed(n) => chars.windows(n).map(|w| w.iter().collect()).collect(),
+            GramSize::MixedScript => {
+                let mut ngrams = Vec::new();
+                for i in 0..chars.len().saturating_sub(1) {
+                    let window = &chars[i..cmp::min(i + 3, chars.len())];
+                    let all_ascii = window.iter().all(char::is_ascii);
+                    if window.len() == 3 && all_ascii {
+                        ngrams.push(window.iter().collect());
+                    } else if !(open_end && all_ascii) {
+                        ngrams.push(window[..2].iter().collect());
+                    }
+                }
+                ngrams
+            }
+        }
+    }
+
+    fn make_bigrams_of_query(
+        query: &str,
+        normalizer: &Normalizer,
+        gram_size: GramSize,
+    ) -> Vec<String> {
         let mut bigrams: Vec<String> = Vec::new();
         let mut query_iter = query.chars();
         while let Some(s) = Self::get_wildcard_part(&mut query_iter) {
-            bigrams.extend(Self::make_bigrams(&s));
+            // The part is padded unless a wildcard is next to it.
+            let open_start = !s.starts_with(' ');
+            let open_end = !s.ends_with(' ');
+            bigrams.extend(Self::make_ngrams(
+                &normalizer.normalize_part(&s, open_start, open_end),
+                gram_size,
+                open_end,
+            ));
         }
         bigrams
     }
 
+    // Packs an n-gram of up to three characters into the 21-bit fields of an
+    // integer from the most significant one, so that the codes are ordered as
+    // the n-grams are. The top bit is left unused.
+    pub fn ngram_code(ngram: &str) -> i64 {
+        ngram.chars().enumerate().fold(0, |code, (i, c)| {
+            code | i64::from(u32::from(c)) << (42 - 21 * i)
+        })
+    }
+
+    // Inverse of ngram_code(). Returns None for an invalid code.
+    pub fn ngram_of_code(code: i64) -> Option<String> {
+        let mut ngram = String::new();
+        for shift in [42, 21, 0] {
+            match (code >> shift) & 0x1fffff {
+                0 => break,
+                field => ngram.push(char::from_u32(field as u32)?),
+            }
+        }
+        (!ngram.is_empty() && Self::ngram_code(&ngram) == code).then_some(ngram)
+    }
+
     fn remove_duplicate_bigms(mut bigrams: Vec<String>, pmatch: bool) -> Self {
         let original_len = bigrams.len();
         bigrams.sort();
@@ -131,3 +244,207 @@ impl BigramList {
         Some(res)
     }
 }
+
+// Step of the normalization applied to texts before bigrams are produced
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum NormalizeStep {
+    // Unicode normalization form KC
+    Nfkc,
+    // Lower-casing of each character
+    Casefold,
+    // Folding of full-width ASCII into half-width, and half-width katakana
+    // into full-width
+    Width,
+    // Folding of katakana into hiragana
+    Kana,
+    // Folding of small kana into normal-sized ones
+    SmallKana,
+    // Removal of prolonged sound marks
+    LongVowel,
+}
+
+impl NormalizeStep {
+    fn apply(self, text: &str) -> String {
+        match self {
+            Self::Nfkc => text.nfkc().collect(),
+            Self::Casefold => lowercase(text),
+            Self::Width => fold_width(text),
+            Self::Kana => text.chars().map(fold_katakana).collect(),
+            Self::SmallKana => text.chars().map(fold_small_kana).collect(),
+            Self::LongVowel => text.chars().filter(|c| *c != 'ー').collect(),
+        }
+    }
+
+    // Whether the step can combine several characters into one
+    fn is_composing(self) -> bool {
+        matches!(self, Self::Nfkc | Self::Width)
+    }
+}
+
+// Normalization pipeline, e.g. 'nfkc,casefold', which applies the steps in
+// the given order. 'none' or an empty string means no normalization.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Normalizer {
+    steps: Vec<NormalizeStep>,
+}
+
+impl Normalizer {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut steps = Vec::new();
+        for name in spec.split(',').map(str::trim) {
+            let step = match name.to_lowercase().as_str() {
+                "" | "none" => continue,
+                "nfkc" => NormalizeStep::Nfkc,
+                "casefold" => NormalizeStep::Casefold,
+                "width" => NormalizeStep::Width,
+                "kana" => NormalizeStep::Kana,
+                "small_kana" => NormalizeStep::SmallKana,
+                "long_vowel" => NormalizeStep::LongVowel,
+                _ => return Err(format!("unrecognized normalization step: \"{name}\"")),
+            };
+            steps.push(step);
+        }
+        Ok(Self { steps })
+    }
+
+    // Appends lower-casing to the end of the pipeline.
+    pub fn casefold(mut self) -> Self {
+        self.steps.push(NormalizeStep::Casefold);
+        self
+    }
+
+    // Appends the folding of katakana into hiragana to the end of the pipeline.
+    pub fn fold_kana(mut self) -> Self {
+        self.steps.push(NormalizeStep::Kana);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    pub fn normalize<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut text = Cow::Borrowed(text);
+        for step in &self.steps {
+            text = Cow::Owned(step.apply(&text));
+        }
+        text
+    }
+
+    // Normalizes a part of a text, which may be preceded or followed by other
+    // characters on the open sides. The composing steps could combine the
+    // characters there with the ones out of the part, so they are dropped up
+    // to the nearest boundary of the composition. Each part then normalizes
+    // into a substring of what the whole text normalizes into.
+    pub fn normalize_part<'a>(
+        &self,
+        text: &'a str,
+        open_start: bool,
+        open_end: bool,
+    ) -> Cow<'a, str> {
+        let mut text = Cow::Borrowed(text);
+        for step in &self.steps {
+            let mut normalized = step.apply(&text);
+            if step.is_composing() {
+                normalized = trim_to_boundaries(&normalized, open_start, open_end).to_owned();
+            }
+            text = Cow::Owned(normalized);
+        }
+        text
+    }
+}
+
+// Drops the characters which the composition could combine with the ones
+// before the start or after the end of the text.
+fn trim_to_boundaries(text: &str, open_start: bool, open_end: bool) -> &str {
+    let mut text = text;
+    if open_start {
+        text = text.find(has_boundary_before).map_or("", |i| &text[i..]);
+    }
+    if open_end {
+        text = match text.char_indices().rfind(|(_, c)| has_boundary_after(*c)) {
+            Some((i, c)) => &text[..i + c.len_utf8()],
+            None => "",
+        };
+    }
+    text
+}
+
+// Whether NFKC never combines the character with the ones before it
+fn has_boundary_before(c: char) -> bool {
+    canonical_combining_class(c) == 0 && is_nfkc_quick(iter::once(c)) == IsNormalized::Yes
+}
+
+// Whether NFKC never combines the character with the ones after it, i.e. it
+// doesn't compose with any character that can follow another in a composition.
+// Such characters are combining marks and conjoining jamo, which are all in the
+// BMP and the SMP.
+fn has_boundary_after(c: char) -> bool {
+    static SECOND_CHARS: OnceLock<Vec<char>> = OnceLock::new();
+    let second_chars = SECOND_CHARS.get_or_init(|| {
+        ('\0'..='\u{1FFFF}')
+            .filter(|c| is_nfkc_quick(iter::once(*c)) == IsNormalized::Maybe)
+            .collect()
+    });
+    has_boundary_before(c)
+        && second_chars
+            .iter()
+            .all(|second| compose(c, *second).is_none())
+}
+
+// Lower-cases the characters one by one, unlike str::to_lowercase(), so that a
+// part of a text is lower-cased into the same characters as in the whole text,
+// e.g. a capital sigma isn't turned into the final form at the end of a part.
+// Each character is mapped to one character like lower() of PostgreSQL does,
+// e.g. 'İ' into 'i' rather than "i̇", so that the keys match what ILIKE does.
+pub fn lowercase(text: &str) -> String {
+    text.chars()
+        .map(|c| c.to_lowercase().next().unwrap_or(c))
+        .collect()
+}
+
+fn fold_width(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    let mut halfwidth_kana = String::new();
+    for c in text.chars() {
+        if ('\u{FF61}'..='\u{FF9F}').contains(&c) {
+            // Half-width katakana are composed with the following sound marks
+            // into full-width ones by NFKC.
+            halfwidth_kana.push(c);
+            continue;
+        }
+        res.extend(halfwidth_kana.nfkc());
+        halfwidth_kana.clear();
+        match c {
+            '\u{FF01}'..='\u{FF5E}' => res.push(char::from_u32(c as u32 - 0xFEE0).unwrap()),
+            '\u{3000}' => res.push(' '),
+            _ => res.push(c),
+        }
+    }
+    res.extend(halfwidth_kana.nfkc());
+    res
+}
+
+fn fold_katakana(c: char) -> char {
+    match c {
+        // From small a to small ke, and iteration marks
+        '\u{30A1}'..='\u{30F6}' | 'ヽ' | 'ヾ' => char::from_u32(c as u32 - 0x60).unwrap(),
+        _ => c,
+    }
+}
+
+fn fold_small_kana(c: char) -> char {
+    match c {
+        'ぁ' | 'ぃ' | 'ぅ' | 'ぇ' | 'ぉ' | 'っ' | 'ゃ' | 'ゅ' | 'ょ' | 'ゎ' => {
+            char::from_u32(c as u32 + 1).unwrap()
+        }
+        'ァ' | 'ィ' | 'ゥ' | 'ェ' | 'ォ' | 'ッ' | 'ャ' | 'ュ' | 'ョ' | 'ヮ' => {
+            char::from_u32(c as u32 + 1).unwrap()
+        }
+        'ゕ' => 'か',
+        'ゖ' => 'け',
+        'ヵ' => 'カ',
+        'ヶ' => 'ケ',
+        _ => c,
+    }
+}
@@ -0,0 +1,132 @@
+use pgrx::pg_sys;
+
+// Boolean expression of the bigram keys that a matching value must include.
+// Leaves are bigrams while the expression is being built, and are replaced
+// with indexes into the array of extracted keys afterwards.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BigramExpr<T = usize> {
+    True,
+    Leaf(T),
+    And(Vec<BigramExpr<T>>),
+    Or(Vec<BigramExpr<T>>),
+}
+
+impl<T: PartialEq + Clone> BigramExpr<T> {
+    pub fn and(exprs: Vec<Self>) -> Self {
+        let mut exprs: Vec<Self> = exprs
+            .into_iter()
+            .filter(|expr| *expr != Self::True)
+            .collect();
+        match exprs.len() {
+            0 => Self::True,
+            1 => exprs.pop().unwrap(),
+            _ => Self::And(exprs),
+        }
+    }
+
+    pub fn or(mut exprs: Vec<Self>) -> Self {
+        if exprs.is_empty() || exprs.contains(&Self::True) {
+            Self::True
+        } else if exprs.len() == 1 {
+            exprs.pop().unwrap()
+        } else {
+            Self::Or(exprs)
+        }
+    }
+
+    // Replaces each leaf with the expression returned by the function.
+    pub fn map_leaves<U>(&self, f: &impl Fn(&T) -> BigramExpr<U>) -> BigramExpr<U> {
+        match self {
+            Self::True => BigramExpr::True,
+            Self::Leaf(key) => f(key),
+            Self::And(exprs) => BigramExpr::And(exprs.iter().map(|e| e.map_leaves(f)).collect()),
+            Self::Or(exprs) => BigramExpr::Or(exprs.iter().map(|e| e.map_leaves(f)).collect()),
+        }
+    }
+
+    // Estimates the fraction of the values satisfying the expression from the
+    // fraction of the values including each key. Keys of a matching value
+    // tend to appear together, so an AND is as selective as its rarest
+    // operand rather than their product.
+    pub fn estimate(&self, frequency: &impl Fn(&T) -> f64) -> f64 {
+        match self {
+            Self::True => 1.0,
+            Self::Leaf(key) => frequency(key),
+            Self::And(exprs) => exprs
+                .iter()
+                .map(|expr| expr.estimate(frequency))
+                .fold(1.0, f64::min),
+            Self::Or(exprs) => exprs
+                .iter()
+                .map(|expr| expr.estimate(frequency))
+                .sum::<f64>()
+                .min(1.0),
+        }
+    }
+
+    // Replaces the leaves with indexes into the returned array of keys, where
+    // each distinct key appears only once.
+    pub fn index_keys(&self) -> (BigramExpr, Vec<T>) {
+        let mut keys = Vec::new();
+        let expr = self.index_keys_into(&mut keys);
+        (expr, keys)
+    }
+
+    fn index_keys_into(&self, keys: &mut Vec<T>) -> BigramExpr {
+        match self {
+            Self::True => BigramExpr::True,
+            Self::Leaf(key) => match keys.iter().position(|k| k == key) {
+                Some(i) => BigramExpr::Leaf(i),
+                None => {
+                    keys.push(key.clone());
+                    BigramExpr::Leaf(keys.len() - 1)
+                }
+            },
+            Self::And(exprs) => BigramExpr::And(
+                exprs
+                    .iter()
+                    .map(|expr| expr.index_keys_into(keys))
+                    .collect(),
+            ),
+            Self::Or(exprs) => BigramExpr::Or(
+                exprs
+                    .iter()
+                    .map(|expr| expr.index_keys_into(keys))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl BigramExpr {
+    // Evaluates the expression. Keys that were not used for the index search
+    // (see pg_bigmr.gin_key_limit) are assumed to be present.
+    pub fn eval(&self, check: &[bool]) -> bool {
+        match self {
+            Self::True => true,
+            Self::Leaf(i) => check.get(*i).copied().unwrap_or(true),
+            Self::And(exprs) => exprs.iter().all(|expr| expr.eval(check)),
+            Self::Or(exprs) => exprs.iter().any(|expr| expr.eval(check)),
+        }
+    }
+
+    // Evaluates the expression in ternary logic.
+    pub fn eval_ternary(&self, check: &[pg_sys::GinTernaryValue]) -> u32 {
+        let (exprs, dominant, other) = match self {
+            Self::True => return pg_sys::GIN_TRUE,
+            Self::Leaf(i) => return check.get(*i).map_or(pg_sys::GIN_MAYBE, |chk| *chk as u32),
+            Self::And(exprs) => (exprs, pg_sys::GIN_FALSE, pg_sys::GIN_TRUE),
+            Self::Or(exprs) => (exprs, pg_sys::GIN_TRUE, pg_sys::GIN_FALSE),
+        };
+
+        // A false operand decides an AND, and a true operand decides an OR.
+        let values: Vec<u32> = exprs.iter().map(|expr| expr.eval_ternary(check)).collect();
+        if values.contains(&dominant) {
+            dominant
+        } else if values.contains(&pg_sys::GIN_MAYBE) {
+            pg_sys::GIN_MAYBE
+        } else {
+            other
+        }
+    }
+}
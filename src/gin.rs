@@ -0,0 +1,525 @@
+use std::{ffi::CStr, mem, slice};
+
+use pgrx::{
+    callconv::{Arg, ArgAbi},
+    pg_sys,
+    pgrx_sql_entity_graph::metadata::{
+        ArgumentError, Returns, ReturnsError, SqlMapping, SqlTranslatable,
+    },
+    prelude::*,
+};
+
+use crate::{bigram::BigramList, CASEFOLD_KEY_PREFIX};
+
+// Page numbers of fixed-location pages
+const GIN_METAPAGE_BLKNO: u32 = 0;
+const GIN_ROOT_BLKNO: u32 = 1;
+
+// Macros for buffer lock/unlock operations
+const GIN_SHARE: i32 = 1;
+
+const INVALID_BLOCK_NUMBER: u32 = u32::MAX;
+const FIRST_OFFSET_NUMBER: u16 = 1;
+
+// Flags of GinPageOpaqueData
+const GIN_LEAF: u16 = 1 << 1;
+const GIN_DELETED: u16 = 1 << 2;
+const GIN_META: u16 = 1 << 3;
+const GIN_COMPRESSED: u16 = 1 << 7;
+
+// Number of the items of an entry tuple telling that its posting list is
+// stored in a posting tree
+const GIN_TREE_POSTING: u16 = 0xffff;
+
+// Mirror of GinPageOpaqueData, the special space of every GIN page
+#[repr(C)]
+struct GinPageOpaqueData {
+    rightlink: pg_sys::BlockNumber,
+    maxoff: pg_sys::OffsetNumber,
+    flags: u16,
+}
+
+// Mirror of GinMetaPageData, the contents of the metapage, which the bindings
+// of pgrx don't have since they don't include access/ginblock.h. Its layout
+// has not changed since PostgreSQL 9.4, i.e. it's the same in all the
+// supported versions. The offsets checked below at compile time are copied by
+// hand from ginblock.h, so they only catch mistakes in this mirror, e.g. in
+// the padding, and have to be checked again for a new major version.
+#[cfg(not(any(
+    feature = "pg12",
+    feature = "pg13",
+    feature = "pg14",
+    feature = "pg15",
+    feature = "pg16",
+    feature = "pg17"
+)))]
+compile_error!("the layout of GinMetaPageData is unknown for this PostgreSQL version");
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct GinMetaPageData {
+    // First and last pages of the pending list
+    pub head: pg_sys::BlockNumber,
+    pub tail: pg_sys::BlockNumber,
+    // Free space in bytes in the last page of the pending list
+    pub tail_free_size: u32,
+    // Number of the pages and the heap tuples in the pending list
+    pub n_pending_pages: pg_sys::BlockNumber,
+    pub n_pending_heap_tuples: i64,
+    // Statistics updated by VACUUM
+    pub n_total_pages: pg_sys::BlockNumber,
+    pub n_entry_pages: pg_sys::BlockNumber,
+    pub n_data_pages: pg_sys::BlockNumber,
+    pub n_entries: i64,
+    // Version of the index format, 2 unless upgraded from 9.3 or before
+    pub gin_version: i32,
+}
+
+const _: () = {
+    assert!(mem::offset_of!(GinMetaPageData, n_pending_pages) == 12);
+    assert!(mem::offset_of!(GinMetaPageData, n_pending_heap_tuples) == 16);
+    assert!(mem::offset_of!(GinMetaPageData, n_entries) == 40);
+    assert!(mem::offset_of!(GinMetaPageData, gin_version) == 48);
+    assert!(mem::size_of::<GinMetaPageData>() == 56);
+};
+
+// Mirror of PostingItem, the downlink of a non-leaf page of a posting tree
+#[repr(C)]
+struct PostingItem {
+    child_blkno: pg_sys::BlockIdData,
+    key: pg_sys::ItemPointerData,
+}
+
+// Mirror of the header of GinPostingList, a compressed segment of the item
+// pointers on a leaf page of a posting tree, followed by nbytes of the
+// varbyte-encoded deltas of the rest of the items
+#[repr(C)]
+struct GinPostingList {
+    first: pg_sys::ItemPointerData,
+    nbytes: u16,
+}
+
+const fn maxalign(len: usize) -> usize {
+    let align = pg_sys::MAXIMUM_ALIGNOF as usize;
+    (len + align - 1) & !(align - 1)
+}
+
+const fn shortalign(len: usize) -> usize {
+    (len + 1) & !1
+}
+
+fn block_number(block_id: &pg_sys::BlockIdData) -> pg_sys::BlockNumber {
+    ((block_id.bi_hi as u32) << 16) | block_id.bi_lo as u32
+}
+
+// Page of a GIN index, which is share-locked while this is alive
+struct GinPage {
+    buffer: pg_sys::Buffer,
+    page: *const u8,
+}
+
+impl GinPage {
+    unsafe fn read(index: pg_sys::Relation, blkno: pg_sys::BlockNumber) -> Self {
+        let buffer = pg_sys::ReadBuffer(index, blkno);
+        pg_sys::LockBuffer(buffer, GIN_SHARE);
+        let page = pg_sys::BufferGetPage(buffer) as *const u8;
+        Self { buffer, page }
+    }
+
+    unsafe fn header(&self) -> &pg_sys::PageHeaderData {
+        &*(self.page as *const pg_sys::PageHeaderData)
+    }
+
+    unsafe fn opaque(&self) -> &GinPageOpaqueData {
+        &*(self.page.add(self.header().pd_special as usize) as *const GinPageOpaqueData)
+    }
+
+    unsafe fn has_flag(&self, flag: u16) -> bool {
+        self.opaque().flags & flag != 0
+    }
+
+    // PageGetContents()
+    unsafe fn contents(&self) -> *const u8 {
+        self.page
+            .add(maxalign(mem::offset_of!(pg_sys::PageHeaderData, pd_linp)))
+    }
+
+    // Number of the line pointers of an entry page
+    unsafe fn max_offset(&self) -> u16 {
+        let header = self.header();
+        ((header.pd_lower as usize - mem::offset_of!(pg_sys::PageHeaderData, pd_linp))
+            / mem::size_of::<pg_sys::ItemIdData>()) as u16
+    }
+
+    // PageGetItem() of an entry page
+    unsafe fn item(&self, offnum: u16) -> pg_sys::IndexTuple {
+        let item_id = &self.header().pd_linp.as_slice(offnum as usize)[offnum as usize - 1];
+        self.page.add(item_id.lp_off() as usize) as pg_sys::IndexTuple
+    }
+
+    // GinDataPageGetData()
+    unsafe fn data(&self) -> *const u8 {
+        self.contents()
+            .add(maxalign(mem::size_of::<pg_sys::ItemPointerData>()))
+    }
+
+    // Returns the number of the item pointers on a leaf page of a posting
+    // tree. Each segment holds its first item and one more for each byte
+    // ending a varbyte-encoded delta.
+    unsafe fn count_data_items(&self) -> i64 {
+        if !self.has_flag(GIN_COMPRESSED) {
+            // Pages of the indexes upgraded from 9.3 or before hold plain
+            // arrays of the item pointers.
+            return self.opaque().maxoff as i64;
+        }
+        let end = self.page.add(self.header().pd_lower as usize);
+        let mut segment = self.data();
+        let mut count = 0;
+        while segment < end {
+            let nbytes = (*(segment as *const GinPostingList)).nbytes as usize;
+            let bytes =
+                slice::from_raw_parts(segment.add(mem::size_of::<GinPostingList>()), nbytes);
+            count += 1 + bytes.iter().filter(|b| **b & 0x80 == 0).count() as i64;
+            segment = segment.add(mem::size_of::<GinPostingList>() + shortalign(nbytes));
+        }
+        count
+    }
+}
+
+impl Drop for GinPage {
+    fn drop(&mut self) {
+        unsafe { pg_sys::UnlockReleaseBuffer(self.buffer) };
+    }
+}
+
+// Opens the GIN index to read its pages directly, which must be closed by
+// pg_sys::relation_close() with AccessShareLock.
+pub unsafe fn open_gin_index(index_oid: pg_sys::Oid) -> pg_sys::Relation {
+    let index_rel = pg_sys::relation_open(index_oid, pg_sys::AccessShareLock as _);
+    let pg_class_entry = &*(*index_rel).rd_rel;
+
+    if pg_class_entry.relkind != pg_sys::RELKIND_INDEX as i8
+        || pg_class_entry.relam != pg_sys::GIN_AM_OID
+    {
+        pgrx::error!(
+            "relation \"{}\" is not a GIN index",
+            name_data_to_str(&pg_class_entry.relname)
+        );
+    };
+
+    // Reject attempts to read non-local temporary relations; we would be
+    // likely to get wrong data since we have no visibility into the owning
+    // session's local buffers.
+    if pg_class_entry.relpersistence == 't' as i8 && !(*index_rel).rd_islocaltemp {
+        pgrx::error!("cannot access temporary indexes of other sessions");
+    };
+
+    index_rel
+}
+
+// Reads the metapage of the GIN index opened by open_gin_index().
+pub unsafe fn read_metapage(index: pg_sys::Relation) -> GinMetaPageData {
+    let page = GinPage::read(index, GIN_METAPAGE_BLKNO);
+    if !page.has_flag(GIN_META) {
+        pgrx::error!(
+            "index \"{}\" does not have a valid GIN metapage",
+            name_data_to_str(&(*(*index).rd_rel).relname)
+        );
+    }
+    // GinPageGetMeta()
+    *(page.contents() as *const GinMetaPageData)
+}
+
+// Moves the pending list of the GIN index into its main tree with
+// gin_clean_pending_list(), and returns the numbers of the pages and the heap
+// tuples cleaned up. It merges the pending pages in batches fitting in
+// maintenance_work_mem, deleting the ones merged by each batch, but can't be
+// told to stop before the end of the list. The number of the heap tuples is
+// read from the metapage before and after, so it misses the ones inserted
+// concurrently.
+pub unsafe fn flush_pending_list(index_oid: pg_sys::Oid) -> (i32, i64) {
+    let index_rel = open_gin_index(index_oid);
+    let before = read_metapage(index_rel);
+    pg_sys::relation_close(index_rel, pg_sys::AccessShareLock as _);
+
+    let pages = pgrx::direct_function_call::<i64>(
+        pg_sys::gin_clean_pending_list,
+        &[index_oid.into_datum()],
+    )
+    .unwrap_or_default();
+    if pages == 0 {
+        return (0, 0);
+    }
+
+    let index_rel = open_gin_index(index_oid);
+    let after = read_metapage(index_rel);
+    pg_sys::relation_close(index_rel, pg_sys::AccessShareLock as _);
+    (
+        pages as i32,
+        (before.n_pending_heap_tuples - after.n_pending_heap_tuples).max(0),
+    )
+}
+
+// regclass, i.e. the OID of a relation given by its name
+pub struct Regclass(pub pg_sys::Oid);
+
+impl FromDatum for Regclass {
+    unsafe fn from_polymorphic_datum(
+        datum: pg_sys::Datum,
+        is_null: bool,
+        _typoid: pg_sys::Oid,
+    ) -> Option<Self> {
+        pg_sys::Oid::from_datum(datum, is_null).map(Self)
+    }
+}
+
+unsafe impl<'fcx> ArgAbi<'fcx> for Regclass {
+    unsafe fn unbox_arg_unchecked(arg: Arg<'_, 'fcx>) -> Self {
+        let index = arg.index();
+        unsafe {
+            arg.unbox_arg_using_from_datum()
+                .unwrap_or_else(|| panic!("argument {index} must not be null"))
+        }
+    }
+}
+
+unsafe impl SqlTranslatable for Regclass {
+    fn argument_sql() -> Result<SqlMapping, ArgumentError> {
+        Ok(SqlMapping::As(String::from("regclass")))
+    }
+
+    fn return_sql() -> Result<Returns, ReturnsError> {
+        Ok(Returns::One(SqlMapping::As(String::from("regclass"))))
+    }
+}
+
+// Form of the keys of the operator classes of pg_bigmr
+#[derive(Clone, Copy, PartialEq)]
+enum KeyForm {
+    // gin_bigm_ops and gin_bigm_ilike_ops: the bigram, prefixed by NUL if
+    // case-folded
+    Text,
+    // gin_bigm_int_ops, gin_bigm_int_ilike_ops and bigm_ops: the code of the
+    // bigram, with the sign
+    // bit set if case-folded
+    Code,
+}
+
+impl KeyForm {
+    // Returns the form of the keys of the index column, or None if its
+    // operator class doesn't belong to pg_bigmr.
+    unsafe fn of_column(index: pg_sys::Relation, attnum: i16) -> Option<Self> {
+        let extract_value =
+            pg_sys::index_getprocid(index, attnum, pg_sys::GIN_EXTRACTVALUE_PROC as u16);
+        let proname = pg_sys::get_func_name(extract_value);
+        if proname.is_null() {
+            return None;
+        }
+        match CStr::from_ptr(proname).to_str().unwrap_or("") {
+            "gin_extract_value_bigm" | "gin_extract_value_bigm_ilike" => Some(Self::Text),
+            "gin_extract_value_bigm_int"
+            | "gin_extract_value_bigm_int_ilike"
+            | "gin_extract_value_bigm_vector" => Some(Self::Code),
+            _ => None,
+        }
+    }
+
+    // Returns the bigram of the key and whether it's case-folded.
+    unsafe fn decode(self, key: pg_sys::Datum) -> (String, bool) {
+        match self {
+            Self::Text => {
+                let key = String::from_datum(key, false).unwrap();
+                match key.strip_prefix(CASEFOLD_KEY_PREFIX) {
+                    Some(bigram) => (bigram.to_string(), true),
+                    None => (key, false),
+                }
+            }
+            Self::Code => {
+                let code = key.value() as i64;
+                let bigram = BigramList::ngram_of_code(code & i64::MAX).unwrap_or_default();
+                (bigram, code < 0)
+            }
+        }
+    }
+}
+
+// Entry tuple of a GIN index, i.e. a key with its posting list or the root of
+// its posting tree
+struct EntryTuple {
+    attnum: i16,
+    key: Option<pg_sys::Datum>,
+    tid: pg_sys::ItemPointerData,
+}
+
+// Reads the entry tuples of the index from the leftmost leaf page of the
+// entry tree to the rightmost one.
+unsafe fn scan_entries(index: pg_sys::Relation, mut f: impl FnMut(EntryTuple)) {
+    // The entry tuples of a multi-column index start with the column number,
+    // so read them with the descriptor of the pair like initGinState() does.
+    let index_desc = (*index).rd_att;
+    let natts = (*index_desc).natts;
+    let tuple_desc = if natts == 1 {
+        index_desc
+    } else {
+        let tuple_desc = pg_sys::CreateTemplateTupleDesc(2);
+        pg_sys::TupleDescInitEntry(tuple_desc, 1, std::ptr::null(), pg_sys::INT2OID, -1, 0);
+        tuple_desc
+    };
+
+    let mut blkno = GIN_ROOT_BLKNO;
+    loop {
+        let page = GinPage::read(index, blkno);
+        if page.has_flag(GIN_LEAF) {
+            break;
+        }
+        // GinGetDownlink()
+        blkno = block_number(&(*page.item(FIRST_OFFSET_NUMBER)).t_tid.ip_blkid);
+    }
+
+    while blkno != INVALID_BLOCK_NUMBER {
+        pgrx::check_for_interrupts!();
+        let page = GinPage::read(index, blkno);
+        for offnum in FIRST_OFFSET_NUMBER..=page.max_offset() {
+            let itup = page.item(offnum);
+            let attnum = if natts == 1 {
+                1
+            } else {
+                pg_sys::nocache_index_getattr(itup, 1, tuple_desc).value() as i16
+            };
+            // The NULL keys stand for the NULL or empty values.
+            let key = if (*itup).t_info as u32 & pg_sys::INDEX_NULL_MASK != 0 {
+                None
+            } else if natts == 1 {
+                Some(pg_sys::nocache_index_getattr(itup, 1, tuple_desc))
+            } else {
+                let attrs = (*index_desc).attrs.as_slice(natts as usize);
+                let key_type = attrs[attnum as usize - 1].atttypid;
+                pg_sys::TupleDescInitEntry(tuple_desc, 2, std::ptr::null(), key_type, -1, 0);
+                Some(pg_sys::nocache_index_getattr(itup, 2, tuple_desc))
+            };
+            f(EntryTuple {
+                attnum,
+                key,
+                tid: (*itup).t_tid,
+            });
+        }
+        blkno = page.opaque().rightlink;
+    }
+}
+
+// Returns the number of the items in the posting tree.
+unsafe fn count_posting_tree(index: pg_sys::Relation, root: pg_sys::BlockNumber) -> i64 {
+    let mut blkno = root;
+    loop {
+        let page = GinPage::read(index, blkno);
+        if page.has_flag(GIN_LEAF) {
+            break;
+        }
+        blkno = block_number(&(*(page.data() as *const PostingItem)).child_blkno);
+    }
+
+    let mut count = 0;
+    while blkno != INVALID_BLOCK_NUMBER {
+        pgrx::check_for_interrupts!();
+        let page = GinPage::read(index, blkno);
+        if !page.has_flag(GIN_DELETED) {
+            count += page.count_data_items();
+        }
+        blkno = page.opaque().rightlink;
+    }
+    count
+}
+
+// Returns the bigram keys of the index with the number of the rows including
+// them, from the most common one. The columns of a multi-column index are
+// told by attnum, and case-folded keys by casefold. The rows still in the
+// pending list are not counted. The keys tell the contents of the column, so
+// only the users who can read every row of the table can see them.
+#[pg_extern(volatile, parallel_safe)]
+fn pg_bigm_index_stats(
+    index_oid: pg_sys::Oid,
+    limit: default!(Option<i32>, "NULL"),
+) -> TableIterator<
+    'static,
+    (
+        name!(attnum, i16),
+        name!(bigram, String),
+        name!(casefold, bool),
+        name!(postings, i64),
+    ),
+> {
+    if limit.is_some_and(|limit| limit < 0) {
+        pgrx::error!("limit must not be negative");
+    }
+
+    let mut stats = Vec::new();
+    unsafe {
+        let index_rel = open_gin_index(index_oid);
+        let table_oid = (*(*index_rel).rd_index).indrelid;
+        if !can_read_all_rows(table_oid) {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_INSUFFICIENT_PRIVILEGE,
+                format!(
+                    "permission denied for table {}",
+                    CStr::from_ptr(pg_sys::get_rel_name(table_oid)).to_string_lossy()
+                ),
+                "The owner of the table, or the users having SELECT privilege on it without row-level security, can see the keys of its indexes."
+            );
+        }
+        let natts = (*(*index_rel).rd_att).natts;
+        let key_forms: Vec<Option<KeyForm>> = (1..=natts as i16)
+            .map(|attnum| KeyForm::of_column(index_rel, attnum))
+            .collect();
+        if key_forms.iter().all(Option::is_none) {
+            pgrx::error!(
+                "index \"{}\" does not use the operator classes of pg_bigmr",
+                name_data_to_str(&(*(*index_rel).rd_rel).relname)
+            );
+        }
+
+        scan_entries(index_rel, |entry| {
+            let Some(key_form) = key_forms[entry.attnum as usize - 1] else {
+                return;
+            };
+            let Some(key) = entry.key else {
+                return;
+            };
+            let (bigram, casefold) = key_form.decode(key);
+            let postings = if entry.tid.ip_posid == GIN_TREE_POSTING {
+                // GinGetPostingTree()
+                count_posting_tree(index_rel, block_number(&entry.tid.ip_blkid))
+            } else {
+                // GinGetNPosting()
+                entry.tid.ip_posid as i64
+            };
+            stats.push((entry.attnum, bigram, casefold, postings));
+        });
+
+        pg_sys::relation_close(index_rel, pg_sys::AccessShareLock as _);
+    }
+
+    stats.sort_by(|a, b| {
+        b.3.cmp(&a.3)
+            .then_with(|| (a.0, &a.1, a.2).cmp(&(b.0, &b.1, b.2)))
+    });
+    if let Some(limit) = limit {
+        stats.truncate(limit as usize);
+    }
+    TableIterator::new(stats)
+}
+
+// Returns whether the current user owns the table, or has SELECT privilege on
+// it and is not subject to its row-level security.
+unsafe fn can_read_all_rows(table_oid: pg_sys::Oid) -> bool {
+    if crate::is_owner_of_relation(table_oid) {
+        return true;
+    }
+    pg_sys::pg_class_aclcheck(
+        table_oid,
+        pg_sys::GetUserId(),
+        pg_sys::ACL_SELECT as pg_sys::AclMode,
+    ) == pg_sys::AclResult::ACLCHECK_OK
+        && pg_sys::check_enable_rls(table_oid, pg_sys::InvalidOid, true)
+            != pg_sys::CheckEnableRlsResult::RLS_ENABLED as i32
+}
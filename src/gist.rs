@@ -0,0 +1,475 @@
+use std::{cmp, mem};
+
+use pg_sys::Datum;
+use pgrx::{prelude::*, Internal, PgMemoryContexts};
+
+use crate::bigram::BigramList;
+use crate::{gucs, like_query_needs_recheck, show_bigm, similarity_score};
+use crate::{DISTANCE_STRATEGY_NUMBER, LIKE_STRATEGY_NUMBER, SIMILARITY_STRATEGY_NUMBER};
+
+// Signature length in bytes and bits
+const SIGLEN: usize = 64;
+const SIGLENBIT: usize = SIGLEN * 8;
+
+// Flags stored in the first byte of a key
+const ARRKEY: u8 = 0x01;
+const SIGNKEY: u8 = 0x02;
+const ALLISTRUE: u8 = 0x04;
+
+// The first valid offset number of a page
+const FIRST_OFFSET_NUMBER: usize = 1;
+
+// Largest size of a leaf key stored as an array, i.e. TOAST_INDEX_TARGET like
+// the GiST index of tsvector, which is a sixteenth of MaxHeapTupleSize
+const TOAST_INDEX_TARGET: usize = (pg_sys::BLCKSZ as usize
+    - ((mem::offset_of!(pg_sys::PageHeaderData, pd_linp)
+        + mem::size_of::<pg_sys::ItemIdData>()
+        + 7)
+        & !7))
+    / 16;
+
+// Key stored in gist_bigm_ops indexes. Leaf keys hold the exact sorted set of
+// bigram codes of the indexed value, or its signature if the set is larger
+// than TOAST_INDEX_TARGET, while inner keys hold a signature of all the bigrams
+// below them.
+#[derive(Debug, PartialEq)]
+enum GistKey {
+    Array(Vec<i64>),
+    Signature(Vec<u8>),
+    AllTrue,
+}
+
+impl GistKey {
+    fn from_value(value: &str) -> Self {
+        let mut codes = bigram_codes(&show_bigm(value));
+        codes.sort_unstable();
+        let key = Self::Array(codes);
+        if pg_sys::VARHDRSZ + key.to_bytes().len() > TOAST_INDEX_TARGET {
+            return signature_to_key(key.signature());
+        }
+        key
+    }
+
+    // Whether the key can only tell that the bigrams may be present, so that
+    // the heap tuples under it always have to be rechecked
+    fn is_lossy(&self) -> bool {
+        !matches!(self, Self::Array(_))
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        match bytes[0] {
+            ARRKEY => Self::Array(
+                bytes[1..]
+                    .chunks_exact(8)
+                    .map(|code| i64::from_ne_bytes(code.try_into().unwrap()))
+                    .collect(),
+            ),
+            SIGNKEY => Self::Signature(bytes[1..].to_vec()),
+            flag if flag & ALLISTRUE != 0 => Self::AllTrue,
+            flag => pgrx::error!("invalid gist_bigm_ops key flag: {flag}"),
+        }
+    }
+
+    unsafe fn from_datum(datum: Datum) -> Self {
+        Self::from_bytes(<&[u8]>::from_datum(datum, false).unwrap())
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Self::Array(codes) => {
+                let mut bytes = Vec::with_capacity(1 + codes.len() * 8);
+                bytes.push(ARRKEY);
+                codes
+                    .iter()
+                    .for_each(|code| bytes.extend_from_slice(&code.to_ne_bytes()));
+                bytes
+            }
+            Self::Signature(sign) => {
+                let mut bytes = Vec::with_capacity(1 + SIGLEN);
+                bytes.push(SIGNKEY);
+                bytes.extend_from_slice(sign);
+                bytes
+            }
+            Self::AllTrue => vec![SIGNKEY | ALLISTRUE],
+        }
+    }
+
+    fn into_datum(self) -> Datum {
+        self.to_bytes().into_datum().unwrap()
+    }
+
+    // Returns the signature of this key, or None if all the bits are set.
+    fn signature(&self) -> Option<Vec<u8>> {
+        match self {
+            Self::Array(codes) => {
+                let mut sign = vec![0; SIGLEN];
+                codes.iter().for_each(|code| set_bit(&mut sign, *code));
+                Some(sign)
+            }
+            Self::Signature(sign) => Some(sign.clone()),
+            Self::AllTrue => None,
+        }
+    }
+
+    // Number of query bigrams that may be present under this key.
+    fn count_matches(&self, codes: &[i64]) -> usize {
+        match self {
+            Self::Array(key_codes) => codes
+                .iter()
+                .filter(|code| key_codes.binary_search(code).is_ok())
+                .count(),
+            Self::Signature(sign) => codes.iter().filter(|code| get_bit(sign, **code)).count(),
+            Self::AllTrue => codes.len(),
+        }
+    }
+
+    // Array keys know the number of bigrams of the indexed value, so the
+    // similarity can be computed just like bigm_similarity(). Signatures can
+    // only give the upper bound described in gin_bigm_consistent().
+    fn similarity(&self, codes: &[i64]) -> f32 {
+        if codes.is_empty() {
+            return 0.0;
+        }
+        let count = self.count_matches(codes);
+        let len = match self {
+            Self::Array(key_codes) => key_codes.len(),
+            _ => count,
+        };
+        similarity_score(count, codes.len(), len)
+    }
+}
+
+fn bigram_codes(bigrams: &[String]) -> Vec<i64> {
+    bigrams
+        .iter()
+        .map(|bgm| BigramList::ngram_code(bgm))
+        .collect()
+}
+
+fn hash_code(code: i64) -> usize {
+    ((code as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 32) as usize % SIGLENBIT
+}
+
+fn set_bit(sign: &mut [u8], code: i64) {
+    let bit = hash_code(code);
+    sign[bit / 8] |= 1 << (bit % 8);
+}
+
+fn get_bit(sign: &[u8], code: i64) -> bool {
+    let bit = hash_code(code);
+    sign[bit / 8] & (1 << (bit % 8)) != 0
+}
+
+fn count_bits(sign: &[u8]) -> usize {
+    sign.iter().map(|b| b.count_ones() as usize).sum()
+}
+
+fn size_of_true(sign: &Option<Vec<u8>>) -> usize {
+    sign.as_deref().map_or(SIGLENBIT, count_bits)
+}
+
+fn hemdist(a: &Option<Vec<u8>>, b: &Option<Vec<u8>>) -> usize {
+    match (a, b) {
+        (None, None) => 0,
+        (None, sign) | (sign, None) => SIGLENBIT - size_of_true(sign),
+        (Some(a), Some(b)) => a
+            .iter()
+            .zip(b.iter())
+            .map(|(x, y)| (x ^ y).count_ones() as usize)
+            .sum(),
+    }
+}
+
+fn union_signature(sign: &mut Option<Vec<u8>>, other: &Option<Vec<u8>>) {
+    match (sign.as_mut(), other) {
+        (Some(sign), Some(other)) => sign.iter_mut().zip(other).for_each(|(x, y)| *x |= y),
+        _ => *sign = None,
+    }
+}
+
+fn signature_to_key(sign: Option<Vec<u8>>) -> GistKey {
+    match sign {
+        Some(sign) if count_bits(&sign) != SIGLENBIT => GistKey::Signature(sign),
+        _ => GistKey::AllTrue,
+    }
+}
+
+// Query of the consistent and distance functions, which is cached in fn_extra
+// since they are called with the same query for every index entry visited.
+struct QueryCache {
+    strategy_number: i16,
+    query: String,
+    codes: Vec<i64>,
+    // Whether the heap tuple needs to be rechecked against the LIKE query
+    like_needs_recheck: bool,
+}
+
+impl QueryCache {
+    fn new(strategy_number: i16, query: &str) -> Self {
+        let mut like_needs_recheck = false;
+        let codes = match strategy_number {
+            LIKE_STRATEGY_NUMBER => {
+                let bigram_list = BigramList::from_query(query);
+                let codes = bigram_codes(&bigram_list.bigrams);
+                // Leaf keys hold the exact set of bigrams, so the recheck rule
+                // is the same as the one for gin_bigm_ops.
+                like_needs_recheck =
+                    like_query_needs_recheck(query, &bigram_list) || codes.len() != 1;
+                codes
+            }
+            SIMILARITY_STRATEGY_NUMBER => bigram_codes(&BigramList::from_value(query).bigrams),
+            DISTANCE_STRATEGY_NUMBER => bigram_codes(&show_bigm(query)),
+            _ => {
+                pgrx::error!("unrecognized strategy number: {strategy_number}");
+            }
+        };
+        Self {
+            strategy_number,
+            query: query.to_string(),
+            codes,
+            like_needs_recheck,
+        }
+    }
+
+    // Returns the cache of the query, extracting its bigrams again only if the
+    // query differs from the previous one, e.g. on a rescan.
+    unsafe fn get<'a>(
+        fcinfo: pg_sys::FunctionCallInfo,
+        strategy_number: i16,
+        query: &str,
+    ) -> &'a Self {
+        let flinfo = (*fcinfo).flinfo;
+        let mut cache = (*flinfo).fn_extra as *mut Option<Self>;
+        if cache.is_null() {
+            cache = PgMemoryContexts::For((*flinfo).fn_mcxt).leak_and_drop_on_delete(None);
+            (*flinfo).fn_extra = cache.cast();
+        }
+        let cached = (*cache)
+            .as_ref()
+            .is_some_and(|c| c.strategy_number == strategy_number && c.query == query);
+        if !cached {
+            *cache = Some(Self::new(strategy_number, query));
+        }
+        (*cache).as_ref().unwrap()
+    }
+}
+
+unsafe fn gistentryinit(entry: &pg_sys::GISTENTRY, key: Datum) -> Internal {
+    let new_entry = PgMemoryContexts::CurrentMemoryContext.palloc_struct::<pg_sys::GISTENTRY>();
+    (*new_entry).key = key;
+    (*new_entry).rel = entry.rel;
+    (*new_entry).page = entry.page;
+    (*new_entry).offset = entry.offset;
+    (*new_entry).leafkey = false;
+    Internal::from(Some(Datum::from(new_entry)))
+}
+
+unsafe fn entry_vector(entryvec: &Internal) -> &[pg_sys::GISTENTRY] {
+    let entryvec = entryvec.get::<pg_sys::GistEntryVector>().unwrap();
+    entryvec.vector.as_slice(entryvec.n as usize)
+}
+
+#[pg_extern(immutable, parallel_safe, strict)]
+fn gist_bigm_compress(entry: Internal) -> Internal {
+    let entry_ = unsafe { entry.get::<pg_sys::GISTENTRY>().unwrap() };
+
+    if entry_.leafkey {
+        // Replace the indexed value with the set of its bigrams
+        let value = unsafe { <&str>::from_datum(entry_.key, false).unwrap() };
+        let key = GistKey::from_value(value);
+        return unsafe { gistentryinit(entry_, key.into_datum()) };
+    }
+
+    // Shrink the signature to the all-true form if every bit is set
+    if let GistKey::Signature(sign) = unsafe { GistKey::from_datum(entry_.key) } {
+        if count_bits(&sign) == SIGLENBIT {
+            return unsafe { gistentryinit(entry_, GistKey::AllTrue.into_datum()) };
+        }
+    }
+    entry
+}
+
+#[pg_extern(immutable, parallel_safe, strict)]
+fn gist_bigm_decompress(entry: Internal) -> Internal {
+    entry
+}
+
+#[pg_extern(immutable, parallel_safe, strict)]
+fn gist_bigm_consistent(
+    entry: Internal,
+    query: &str,
+    strategy_number: i16,
+    _subtype: pg_sys::Oid,
+    recheck: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> bool {
+    let entry_ = unsafe { entry.get::<pg_sys::GISTENTRY>().unwrap() };
+    let key = unsafe { GistKey::from_datum(entry_.key) };
+    let cache = unsafe { QueryCache::get(fcinfo, strategy_number, query) };
+    let codes = &cache.codes;
+
+    match strategy_number {
+        LIKE_STRATEGY_NUMBER => {
+            unsafe {
+                *recheck.get_mut::<bool>().unwrap() =
+                    key.is_lossy() || (gucs::enable_recheck() && cache.like_needs_recheck);
+            };
+
+            // Check if all extracted bigrams are presented.
+            key.count_matches(codes) == codes.len()
+        }
+        SIMILARITY_STRATEGY_NUMBER => {
+            unsafe {
+                *recheck.get_mut::<bool>().unwrap() = key.is_lossy() || gucs::enable_recheck()
+            };
+
+            !codes.is_empty() && key.similarity(codes) >= gucs::similarity_limit() as f32
+        }
+        _ => {
+            pgrx::error!("unrecognized strategy number: {strategy_number}");
+        }
+    }
+}
+
+#[pg_extern(parallel_safe, stable, strict)]
+fn gist_bigm_distance(
+    entry: Internal,
+    query: &str,
+    strategy_number: i16,
+    _subtype: pg_sys::Oid,
+    recheck: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> f64 {
+    let entry_ = unsafe { entry.get::<pg_sys::GISTENTRY>().unwrap() };
+    let key = unsafe { GistKey::from_datum(entry_.key) };
+
+    match strategy_number {
+        DISTANCE_STRATEGY_NUMBER => {
+            // The distance of array keys is exact, and the one of signatures
+            // is a lower bound of the distances below them.
+            let cache = unsafe { QueryCache::get(fcinfo, strategy_number, query) };
+            unsafe { *recheck.get_mut::<bool>().unwrap() = key.is_lossy() };
+            1.0 - key.similarity(&cache.codes) as f64
+        }
+        _ => {
+            pgrx::error!("unrecognized strategy number: {strategy_number}");
+        }
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, strict)]
+fn gist_bigm_union(entryvec: Internal, size: Internal) -> Vec<u8> {
+    let entries = unsafe { entry_vector(&entryvec) };
+
+    let mut sign = Some(vec![0; SIGLEN]);
+    for entry in entries {
+        let key = unsafe { GistKey::from_datum(entry.key) };
+        union_signature(&mut sign, &key.signature());
+        if sign.is_none() {
+            break;
+        }
+    }
+
+    let bytes = signature_to_key(sign).to_bytes();
+    unsafe { *size.get_mut::<i32>().unwrap() = bytes.len() as i32 };
+    bytes
+}
+
+#[pg_extern(immutable, parallel_safe, strict)]
+fn gist_bigm_same(key1: &[u8], key2: &[u8], result: Internal) -> Internal {
+    // Keys are stored in a canonical form, so it's enough to compare bytes.
+    unsafe { *result.get_mut::<bool>().unwrap() = key1 == key2 };
+    result
+}
+
+#[pg_extern(immutable, parallel_safe, strict)]
+fn gist_bigm_penalty(origentry: Internal, newentry: Internal, penalty: Internal) -> Internal {
+    let (origkey, newkey) = unsafe {
+        (
+            GistKey::from_datum(origentry.get::<pg_sys::GISTENTRY>().unwrap().key),
+            GistKey::from_datum(newentry.get::<pg_sys::GISTENTRY>().unwrap().key),
+        )
+    };
+
+    // The penalty is the number of signature bits that differ between keys.
+    let dist = hemdist(&origkey.signature(), &newkey.signature());
+    unsafe { *penalty.get_mut::<f32>().unwrap() = dist as f32 };
+    penalty
+}
+
+#[pg_extern(immutable, parallel_safe, strict)]
+fn gist_bigm_picksplit(entryvec: Internal, splitvec: Internal) -> Internal {
+    let entries = unsafe { entry_vector(&entryvec) };
+    let maxoff = entries.len() - 1;
+
+    let signs: Vec<Option<Vec<u8>>> = (FIRST_OFFSET_NUMBER..=maxoff)
+        .map(|i| unsafe { GistKey::from_datum(entries[i].key) }.signature())
+        .collect();
+
+    // Pick the two keys that are farthest from each other as seeds.
+    let (mut seed_1, mut seed_2) = (0, 1);
+    let mut waste = None;
+    for i in 0..signs.len() {
+        for j in i + 1..signs.len() {
+            let dist = hemdist(&signs[i], &signs[j]);
+            if waste.is_none_or(|waste| dist > waste) {
+                waste = Some(dist);
+                seed_1 = i;
+                seed_2 = j;
+            }
+        }
+    }
+
+    // Handle the remaining keys in the order of how strongly they prefer one
+    // seed over the other, so that ambiguous keys are placed last.
+    let mut order: Vec<usize> = (0..signs.len()).collect();
+    order.sort_by_cached_key(|&i| {
+        let diff =
+            hemdist(&signs[i], &signs[seed_1]) as i64 - hemdist(&signs[i], &signs[seed_2]) as i64;
+        cmp::Reverse(diff.abs())
+    });
+
+    let mut left = Vec::with_capacity(signs.len());
+    let mut right = Vec::with_capacity(signs.len());
+    let mut union_l = signs[seed_1].clone();
+    let mut union_r = signs[seed_2].clone();
+    for i in order {
+        let goes_left = if i == seed_1 {
+            true
+        } else if i == seed_2 {
+            false
+        } else {
+            // Prefer the side that grows less, breaking ties towards the
+            // smaller side to keep the split balanced.
+            let size_l = hemdist(&union_l, &signs[i]);
+            let size_r = hemdist(&union_r, &signs[i]);
+            size_l < size_r || (size_l == size_r && left.len() <= right.len())
+        };
+
+        if goes_left {
+            union_signature(&mut union_l, &signs[i]);
+            left.push((i + FIRST_OFFSET_NUMBER) as pg_sys::OffsetNumber);
+        } else {
+            union_signature(&mut union_r, &signs[i]);
+            right.push((i + FIRST_OFFSET_NUMBER) as pg_sys::OffsetNumber);
+        }
+    }
+
+    unsafe {
+        let v = splitvec.get_mut::<pg_sys::GIST_SPLITVEC>().unwrap();
+        let spl_left = PgMemoryContexts::CurrentMemoryContext
+            .palloc0_slice::<pg_sys::OffsetNumber>(left.len());
+        spl_left.copy_from_slice(&left);
+        let spl_right = PgMemoryContexts::CurrentMemoryContext
+            .palloc0_slice::<pg_sys::OffsetNumber>(right.len());
+        spl_right.copy_from_slice(&right);
+
+        v.spl_left = spl_left.as_mut_ptr();
+        v.spl_nleft = left.len() as i32;
+        v.spl_ldatum = signature_to_key(union_l).into_datum();
+        v.spl_right = spl_right.as_mut_ptr();
+        v.spl_nright = right.len() as i32;
+        v.spl_rdatum = signature_to_key(union_r).into_datum();
+    }
+    splitvec
+}
@@ -1,44 +1,169 @@
-use pgrx::{GucContext, GucFlags, GucRegistry, GucSetting};
-use std::ffi::CStr;
+use pgrx::{pg_guard, pg_sys, GucContext, GucFlags, GucRegistry, GucSetting, PostgresGucEnum};
+use std::ffi::{c_void, CStr};
+use std::ptr::addr_of_mut;
 
-static ENABLE_RECHECK: GucSetting<bool> = GucSetting::<bool>::new(true);
-static GIN_KEY_LIMIT: GucSetting<i32> = GucSetting::<i32>::new(0);
-static SIMILARITY_LIMIT: GucSetting<f64> = GucSetting::<f64>::new(0.3);
+// The parameters having pg_bigm.* aliases keep the value of each name in a
+// variable of its own, since defining a parameter resets its variable to the
+// boot value. The assign hook shared by both names sets both variables.
+static mut ENABLE_RECHECK: bool = true;
+static mut PG_BIGM_ENABLE_RECHECK: bool = true;
+static mut GIN_KEY_LIMIT: i32 = 0;
+static mut PG_BIGM_GIN_KEY_LIMIT: i32 = 0;
+static mut SIMILARITY_LIMIT: f64 = 0.3;
+static mut PG_BIGM_SIMILARITY_LIMIT: f64 = 0.3;
+static GIN_KEY_SELECTION: GucSetting<GinKeySelection> =
+    GucSetting::<GinKeySelection>::new(GinKeySelection::First);
+static SIMILARITY_FORMULA: GucSetting<SimilarityFormula> =
+    GucSetting::<SimilarityFormula>::new(SimilarityFormula::Max);
+static WORD_SIMILARITY_LIMIT: GucSetting<f64> = GucSetting::<f64>::new(0.6);
+static STRICT_WORD_SIMILARITY_LIMIT: GucSetting<f64> = GucSetting::<f64>::new(0.5);
+static FLUSH_NAPTIME: GucSetting<i32> = GucSetting::<i32>::new(60);
+static FLUSH_THRESHOLD: GucSetting<i32> = GucSetting::<i32>::new(128);
+static FLUSH_DATABASES: GucSetting<Option<&'static CStr>> =
+    GucSetting::<Option<&'static CStr>>::new(None);
 static BIGM_LAST_UPDATE: GucSetting<Option<&'static CStr>> =
     GucSetting::<Option<&'static CStr>>::new(Some(c"2024.06.06"));
+static PG_BIGM_COMPATIBILITY: GucSetting<bool> = GucSetting::<bool>::new(false);
+// String settings can't share their values, since each setting frees the old
+// values of its own.
+static PG_BIGM_LAST_UPDATE: GucSetting<Option<&'static CStr>> =
+    GucSetting::<Option<&'static CStr>>::new(Some(c"2024.06.06"));
+
+// Keys used for GIN index search when there are more than
+// pg_bigmr.gin_key_limit of them
+#[derive(PostgresGucEnum, Clone, Copy, PartialEq, Debug)]
+pub enum GinKeySelection {
+    // The first ones in the sorted order
+    First,
+    // The least frequent ones according to pg_bigm_key_stats, which is only
+    // refreshed by pg_bigm_collect_key_stats()
+    Rarest,
+}
+
+// Formula of the similarity between two strings, where c is the number of
+// common bigrams and len1 and len2 are the numbers of bigrams of each string
+#[derive(PostgresGucEnum, Clone, Copy, PartialEq, Debug)]
+pub enum SimilarityFormula {
+    // c / max(len1, len2)
+    Max,
+    // c / (len1 + len2 - c), i.e. Jaccard index
+    Union,
+    // 2 * c / (len1 + len2)
+    Dice,
+    // c / min(len1, len2)
+    Overlap,
+}
 
 pub fn init() {
-    GucRegistry::define_bool_guc(
-        "pg_bigmr.enable_recheck",
-        "Recheck that heap tuples fetched from index match the query.",
-        "", // TODO: Set to None, not empty string
-        &ENABLE_RECHECK,
+    unsafe {
+        pg_sys::DefineCustomBoolVariable(
+            c"pg_bigmr.enable_recheck".as_ptr(),
+            c"Recheck that heap tuples fetched from index match the query.".as_ptr(),
+            c"".as_ptr(), // TODO: Set to None, not empty string
+            addr_of_mut!(ENABLE_RECHECK),
+            ENABLE_RECHECK,
+            pg_sys::GucContext::PGC_USERSET,
+            0,
+            None,
+            Some(assign_enable_recheck),
+            None,
+        );
+
+        pg_sys::DefineCustomIntVariable(
+            c"pg_bigmr.gin_key_limit".as_ptr(),
+            c"Sets the maximum number of bi-gram keys allowed to use for GIN index search.".as_ptr(),
+            c"Zero means no limit.".as_ptr(),
+            addr_of_mut!(GIN_KEY_LIMIT),
+            GIN_KEY_LIMIT,
+            0,
+            i32::MAX,
+            pg_sys::GucContext::PGC_USERSET,
+            0,
+            None,
+            Some(assign_gin_key_limit),
+            None,
+        );
+    }
+
+    GucRegistry::define_enum_guc(
+        "pg_bigmr.gin_key_selection",
+        "Sets which bi-gram keys are used for GIN index search when they exceed pg_bigmr.gin_key_limit.",
+        "Valid values are first and rarest, which uses the frequencies collected by pg_bigm_collect_key_stats().",
+        &GIN_KEY_SELECTION,
         GucContext::Userset,
         GucFlags::default(),
     );
 
-    GucRegistry::define_int_guc(
-        "pg_bigmr.gin_key_limit",
-        "Sets the maximum number of bi-gram keys allowed to use for GIN index search.",
-        "Zero means no limit.",
-        &GIN_KEY_LIMIT,
-        0,
-        i32::MAX,
+    unsafe {
+        pg_sys::DefineCustomRealVariable(
+            c"pg_bigmr.similarity_limit".as_ptr(),
+            c"Sets the similarity threshold used by the =% operator.".as_ptr(),
+            c"".as_ptr(), // TODO: Set to None, not empty string
+            addr_of_mut!(SIMILARITY_LIMIT),
+            SIMILARITY_LIMIT,
+            0.0,
+            1.0,
+            pg_sys::GucContext::PGC_USERSET,
+            0,
+            None,
+            Some(assign_similarity_limit),
+            None,
+        );
+    }
+
+    GucRegistry::define_enum_guc(
+        "pg_bigmr.similarity_formula",
+        "Sets the formula used to compute the similarity of two strings.",
+        "Valid values are max, union, dice and overlap.",
+        &SIMILARITY_FORMULA,
         GucContext::Userset,
         GucFlags::default(),
     );
 
     GucRegistry::define_float_guc(
-        "pg_bigmr.similarity_limit",
-        "Sets the similarity threshold used by the =% operator.",
+        "pg_bigmr.word_similarity_limit",
+        "Sets the word similarity threshold used by the <% and %> operators.",
         "", // TODO: Set to None, not empty string
-        &SIMILARITY_LIMIT,
+        &WORD_SIMILARITY_LIMIT,
         0.0,
         1.0,
         GucContext::Userset,
         GucFlags::default(),
     );
 
+    GucRegistry::define_float_guc(
+        "pg_bigmr.strict_word_similarity_limit",
+        "Sets the strict word similarity threshold used by the <<% and %>> operators.",
+        "", // TODO: Set to None, not empty string
+        &STRICT_WORD_SIMILARITY_LIMIT,
+        0.0,
+        1.0,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_int_guc(
+        "pg_bigmr.flush_naptime",
+        "Sets the time to sleep between the rounds of the pending list flush worker.",
+        "",
+        &FLUSH_NAPTIME,
+        1,
+        i32::MAX / 1000,
+        GucContext::Sighup,
+        GucFlags::UNIT_S,
+    );
+
+    GucRegistry::define_int_guc(
+        "pg_bigmr.flush_threshold",
+        "Sets the size of the pending list above which the flush worker flushes it.",
+        "",
+        &FLUSH_THRESHOLD,
+        0,
+        i32::MAX,
+        GucContext::Sighup,
+        GucFlags::UNIT_BLOCKS,
+    );
+
     // Can't be set in postgresql.conf
     GucRegistry::define_string_guc(
         "pg_bigmr.last_update",
@@ -53,14 +178,155 @@ pub fn init() {
     // MarkGUCPrefixReserved("pg_bigmr");
 }
 
+// Defines the parameters only read by the postmaster, which can't be defined
+// after startup, i.e. unless pg_bigmr is loaded by shared_preload_libraries.
+pub fn init_preload() {
+    GucRegistry::define_string_guc(
+        "pg_bigmr.flush_databases",
+        "Sets the comma-separated list of the databases whose pending lists are flushed by the flush workers.",
+        "Empty means no flush worker is started.",
+        &FLUSH_DATABASES,
+        GucContext::Postmaster,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_bool_guc(
+        "pg_bigmr.pg_bigm_compatibility",
+        "Defines the pg_bigm.* aliases of the pg_bigmr.* parameters.",
+        "They can't be defined while pg_bigm is loaded.",
+        &PG_BIGM_COMPATIBILITY,
+        GucContext::Postmaster,
+        GucFlags::default(),
+    );
+    if PG_BIGM_COMPATIBILITY.get() {
+        define_pg_bigm_aliases();
+    }
+}
+
+// Defines the parameters of pg_bigm as aliases of the pg_bigmr.* ones. Setting
+// either name of a parameter changes the value shown by both, but each name
+// keeps its own source and reset value, e.g. RESET of one name restores the
+// value the other name had when it was set.
+fn define_pg_bigm_aliases() {
+    // The boot values are the current ones of pg_bigmr.*, which the assign
+    // hooks called while defining the aliases would otherwise overwrite.
+    unsafe {
+        pg_sys::DefineCustomBoolVariable(
+            c"pg_bigm.enable_recheck".as_ptr(),
+            c"Recheck that heap tuples fetched from index match the query.".as_ptr(),
+            c"Alias of pg_bigmr.enable_recheck.".as_ptr(),
+            addr_of_mut!(PG_BIGM_ENABLE_RECHECK),
+            ENABLE_RECHECK,
+            pg_sys::GucContext::PGC_USERSET,
+            0,
+            None,
+            Some(assign_enable_recheck),
+            None,
+        );
+
+        pg_sys::DefineCustomIntVariable(
+            c"pg_bigm.gin_key_limit".as_ptr(),
+            c"Sets the maximum number of bi-gram keys allowed to use for GIN index search.".as_ptr(),
+            c"Alias of pg_bigmr.gin_key_limit.".as_ptr(),
+            addr_of_mut!(PG_BIGM_GIN_KEY_LIMIT),
+            GIN_KEY_LIMIT,
+            0,
+            i32::MAX,
+            pg_sys::GucContext::PGC_USERSET,
+            0,
+            None,
+            Some(assign_gin_key_limit),
+            None,
+        );
+
+        pg_sys::DefineCustomRealVariable(
+            c"pg_bigm.similarity_limit".as_ptr(),
+            c"Sets the similarity threshold used by the =% operator.".as_ptr(),
+            c"Alias of pg_bigmr.similarity_limit.".as_ptr(),
+            addr_of_mut!(PG_BIGM_SIMILARITY_LIMIT),
+            SIMILARITY_LIMIT,
+            0.0,
+            1.0,
+            pg_sys::GucContext::PGC_USERSET,
+            0,
+            None,
+            Some(assign_similarity_limit),
+            None,
+        );
+    }
+
+    GucRegistry::define_string_guc(
+        "pg_bigm.last_update",
+        "Shows the last update date of pg_bigm.",
+        "Alias of pg_bigmr.last_update.",
+        &PG_BIGM_LAST_UPDATE,
+        GucContext::Internal,
+        GucFlags::REPORT | GucFlags::DISALLOW_IN_FILE,
+    );
+}
+
+#[pg_guard]
+unsafe extern "C" fn assign_enable_recheck(newval: bool, _extra: *mut c_void) {
+    ENABLE_RECHECK = newval;
+    PG_BIGM_ENABLE_RECHECK = newval;
+}
+
+#[pg_guard]
+unsafe extern "C" fn assign_gin_key_limit(newval: i32, _extra: *mut c_void) {
+    GIN_KEY_LIMIT = newval;
+    PG_BIGM_GIN_KEY_LIMIT = newval;
+}
+
+#[pg_guard]
+unsafe extern "C" fn assign_similarity_limit(newval: f64, _extra: *mut c_void) {
+    SIMILARITY_LIMIT = newval;
+    PG_BIGM_SIMILARITY_LIMIT = newval;
+}
+
 pub fn enable_recheck() -> bool {
-    ENABLE_RECHECK.get()
+    unsafe { ENABLE_RECHECK }
 }
 
 pub fn gin_key_limit() -> i32 {
-    GIN_KEY_LIMIT.get()
+    unsafe { GIN_KEY_LIMIT }
+}
+
+pub fn gin_key_selection() -> GinKeySelection {
+    GIN_KEY_SELECTION.get()
 }
 
 pub fn similarity_limit() -> f64 {
-    SIMILARITY_LIMIT.get()
+    unsafe { SIMILARITY_LIMIT }
+}
+
+pub fn similarity_formula() -> SimilarityFormula {
+    SIMILARITY_FORMULA.get()
+}
+
+pub fn word_similarity_limit() -> f64 {
+    WORD_SIMILARITY_LIMIT.get()
+}
+
+pub fn strict_word_similarity_limit() -> f64 {
+    STRICT_WORD_SIMILARITY_LIMIT.get()
+}
+
+pub fn flush_naptime() -> i32 {
+    FLUSH_NAPTIME.get()
+}
+
+pub fn flush_threshold() -> i32 {
+    FLUSH_THRESHOLD.get()
+}
+
+pub fn flush_databases() -> Vec<String> {
+    let Some(databases) = FLUSH_DATABASES.get() else {
+        return Vec::new();
+    };
+    databases
+        .to_string_lossy()
+        .split(',')
+        .map(|database| database.trim().to_string())
+        .filter(|database| !database.is_empty())
+        .collect()
 }
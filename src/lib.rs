@@ -1,48 +1,181 @@
-use std::{cmp, mem};
+use std::{cmp, collections::HashMap, ffi::CStr, mem, num::NonZeroUsize, ptr};
 
-use bigram::BigramList;
+use bigram::{BigramList, GramSize, Normalizer};
+use expr::BigramExpr;
+use gucs::GinKeySelection;
+use options::IndexOptions;
 use pg_sys::Datum;
-use pgrx::{prelude::*, varlena, Internal, PgMemoryContexts};
+use pgrx::{
+    heap_tuple::PgHeapTuple, htup::heap_getattr_raw, prelude::*, varlena, AllocatedByRust,
+    Internal, PgMemoryContexts,
+};
+use query::BigmQuery;
 
+mod bigm;
 mod bigram;
+mod expr;
+mod gin;
+mod gist;
 mod gucs;
+mod options;
+mod query;
+mod regex;
+mod selfuncs;
+mod worker;
 
 ::pgrx::pg_module_magic!();
 extension_sql_file!("../sql/pg_bigmr--0.1.0.sql", name = "pg_bigmr", finalize);
+extension_sql!(
+    r#"
+-- query of the =% operator carrying its own similarity threshold
+CREATE TYPE bigm_similar_query AS (query text, threshold float4);
+"#,
+    name = "bigm_similar_query",
+);
 
 // operator strategy numbers
 const LIKE_STRATEGY_NUMBER: i16 = 1;
 const SIMILARITY_STRATEGY_NUMBER: i16 = 2;
+const DISTANCE_STRATEGY_NUMBER: i16 = 3;
+const ILIKE_STRATEGY_NUMBER: i16 = 4;
+const REGEX_STRATEGY_NUMBER: i16 = 5;
+const IREGEX_STRATEGY_NUMBER: i16 = 6;
+const WORD_SIMILARITY_STRATEGY_NUMBER: i16 = 7;
+const STRICT_WORD_SIMILARITY_STRATEGY_NUMBER: i16 = 8;
+const SIMILAR_QUERY_STRATEGY_NUMBER: i16 = 9;
+const BIGM_QUERY_STRATEGY_NUMBER: i16 = 10;
+const LIKE_ANY_STRATEGY_NUMBER: i16 = 11;
 
-// Page numbers of fixed-location pages
-const GIN_METAPAGE_BLKNO: u32 = 0;
-
-// Macros for buffer lock/unlock operations
-const GIN_SHARE: i32 = 1;
+// Prefix of the keys holding case-folded bigrams. Text values can't contain
+// NUL characters, so these keys never collide with ordinary bigrams.
+const CASEFOLD_KEY_PREFIX: char = '\0';
 
 #[pg_guard]
 pub extern "C" fn _PG_init() {
     self::gucs::init();
+    if unsafe { pg_sys::process_shared_preload_libraries_in_progress } {
+        self::gucs::init_preload();
+        self::worker::register();
+    }
 }
 
-#[pg_operator(parallel_safe, stable, strict)]
+#[pg_operator(parallel_safe, stable, strict, requires = [bigm_sel])]
 #[opname(=%)]
-#[restrict(contsel)]
+#[restrict(bigm_sel)]
 #[join(contjoinsel)]
 fn bigm_similarity_op(input1: &str, input2: &str) -> bool {
     let similarity = bigm_similarity(input1, input2);
     similarity >= gucs::similarity_limit() as f32
 }
 
+// The =% operator with bigm_similar_query is created in the extension script,
+// since #[pg_operator] can't take a composite type as the right operand.
+#[pg_extern(parallel_safe, stable, strict, requires = ["bigm_similar_query"])]
+fn bigm_similar_query_op(
+    input: &str,
+    query: pgrx::composite_type!("bigm_similar_query"),
+) -> Option<bool> {
+    let (word, threshold) = similar_query_fields(&query);
+    Some(bigm_similarity(input, &word?) >= threshold as f32)
+}
+
+// Returns the search word and the similarity threshold of bigm_similar_query.
+// pg_bigmr.similarity_limit is used if the threshold is NULL.
+fn similar_query_fields(query: &PgHeapTuple<'_, AllocatedByRust>) -> (Option<String>, f64) {
+    let word = query.get_by_name::<String>("query").unwrap_or_else(|e| {
+        pgrx::error!("invalid bigm_similar_query: {e}");
+    });
+    let threshold = query.get_by_name::<f32>("threshold").unwrap_or_else(|e| {
+        pgrx::error!("invalid bigm_similar_query: {e}");
+    });
+    (
+        word,
+        threshold.map_or_else(gucs::similarity_limit, f64::from),
+    )
+}
+
+#[pg_operator(parallel_safe, stable, strict, requires = [bigm_sel])]
+#[opname(<%)]
+#[commutator(%>)]
+#[restrict(bigm_sel)]
+#[join(contjoinsel)]
+fn bigm_word_similarity_op(input1: &str, input2: &str) -> bool {
+    let similarity = bigm_word_similarity(input1, input2);
+    similarity >= gucs::word_similarity_limit() as f32
+}
+
+#[pg_operator(parallel_safe, stable, strict, requires = [bigm_sel])]
+#[opname(%>)]
+#[commutator(<%)]
+#[restrict(bigm_sel)]
+#[join(contjoinsel)]
+fn bigm_word_similarity_commutator_op(input1: &str, input2: &str) -> bool {
+    bigm_word_similarity_op(input2, input1)
+}
+
+#[pg_operator(parallel_safe, stable, strict, requires = [bigm_sel])]
+#[opname(<<%)]
+#[commutator(%>>)]
+#[restrict(bigm_sel)]
+#[join(contjoinsel)]
+fn bigm_strict_word_similarity_op(input1: &str, input2: &str) -> bool {
+    let similarity = bigm_strict_word_similarity(input1, input2);
+    similarity >= gucs::strict_word_similarity_limit() as f32
+}
+
+#[pg_operator(parallel_safe, stable, strict, requires = [bigm_sel])]
+#[opname(%>>)]
+#[commutator(<<%)]
+#[restrict(bigm_sel)]
+#[join(contjoinsel)]
+fn bigm_strict_word_similarity_commutator_op(input1: &str, input2: &str) -> bool {
+    bigm_strict_word_similarity_op(input2, input1)
+}
+
+// Same as LIKE ANY, which can't use the index since it's not an operator, so
+// that all the patterns are searched in one index scan.
+#[pg_operator(immutable, parallel_safe, strict, requires = [bigm_sel])]
+#[opname(~~|)]
+#[restrict(bigm_sel)]
+#[join(contjoinsel)]
+fn bigm_like_any(input: &str, patterns: Array<&str>) -> Option<bool> {
+    let mut result = Some(false);
+    for pattern in patterns.iter() {
+        let Some(pattern) = pattern else {
+            result = None;
+            continue;
+        };
+        let matched = unsafe {
+            pgrx::direct_function_call::<bool>(
+                pg_sys::textlike,
+                &[input.into_datum(), pattern.into_datum()],
+            )
+        };
+        if matched == Some(true) {
+            return Some(true);
+        }
+    }
+    result
+}
+
+#[pg_operator(parallel_safe, stable, strict)]
+#[opname(<->)]
+#[commutator(<->)]
+fn bigm_distance(input1: &str, input2: &str) -> f32 {
+    1.0 - bigm_similarity(input1, input2)
+}
+
 #[pg_extern(immutable, parallel_safe, strict)]
-fn likequery(query: &str) -> String {
-    if query.is_empty() {
-        // TODO:
-        // Due to the specifications of pgrx, NULL cannot be returned,
-        // so it results in an error.
-        pgrx::error!("query cannot be empty");
-    };
+fn likequery(query: &str) -> Option<String> {
+    // Nothing to search for, so the query matches no rows.
+    if query.trim().is_empty() {
+        return None;
+    }
+    Some(like_pattern(query))
+}
 
+// Escapes the query into a LIKE pattern matching the texts including it.
+fn like_pattern(query: &str) -> String {
     // TODO: Handle OOM errors for large queries.
 
     let result = query
@@ -54,16 +187,62 @@ fn likequery(query: &str) -> String {
 
 #[pg_extern(immutable, parallel_safe, strict)]
 fn show_bigm(input: &str) -> Vec<String> {
+    extract_ngrams(input, GramSize::default())
+}
+
+#[pg_extern(immutable, parallel_safe, strict)]
+fn show_ngrams(input: &str, gram_size: i32) -> Vec<String> {
+    if !(1..=3).contains(&gram_size) {
+        pgrx::error!("gram size must be between 1 and 3");
+    }
+    extract_ngrams(input, GramSize::Fixed(gram_size as usize))
+}
+
+fn extract_ngrams(input: &str, gram_size: GramSize) -> Vec<String> {
     // TODO: Handle OOM errors for large queries.
     let trimed_input = input.trim();
     if trimed_input.is_empty() {
         return Vec::new();
     };
-    let bigram_list = BigramList::from_value(trimed_input);
+    let bigram_list = BigramList::from_value_ngrams(trimed_input, gram_size);
     bigram_list.bigrams
 }
 
+// Shows the bigrams produced with the normalization given in the same form
+// as the normalize option of gin_bigm_ops.
+#[pg_extern(immutable, parallel_safe, strict, name = "show_bigm")]
+fn show_bigm_normalized(input: &str, normalize: &str) -> Vec<String> {
+    show_bigm(&bigm_normalize(input, normalize))
+}
+
+// Folds katakana into hiragana, as the kana step of bigm_normalize() and the
+// kana option of gin_bigm_ops do, and optionally small kana and prolonged
+// sound marks, e.g. for an expression index or to preprocess texts.
 #[pg_extern(immutable, parallel_safe, strict)]
+fn bigm_fold_kana(
+    input: &str,
+    small_kana: default!(bool, false),
+    long_vowel: default!(bool, false),
+) -> String {
+    let mut normalize = vec!["kana"];
+    if small_kana {
+        normalize.push("small_kana");
+    }
+    if long_vowel {
+        normalize.push("long_vowel");
+    }
+    bigm_normalize(input, &normalize.join(","))
+}
+
+#[pg_extern(immutable, parallel_safe, strict)]
+fn bigm_normalize(input: &str, normalize: &str) -> String {
+    let normalizer = Normalizer::parse(normalize).unwrap_or_else(|msg| pgrx::error!("{msg}"));
+    normalizer.normalize(input).into_owned()
+}
+
+// The similarity functions are stable since their results depend on
+// pg_bigmr.similarity_formula.
+#[pg_extern(parallel_safe, stable, strict)]
 fn bigm_similarity(input1: &str, input2: &str) -> f32 {
     // explicit test is needed to avoid 0/0 division when both lengths are 0
     if input1.chars().count() == 0 || input2.chars().count() == 0 {
@@ -96,60 +275,232 @@ fn bigm_similarity(input1: &str, input2: &str) -> f32 {
         }
     }
 
-    let max_len = if bigm1.len() > bigm2.len() {
-        bigm1.len()
-    } else {
-        bigm2.len()
-    };
-    count as f32 / max_len as f32
+    similarity_score(count, bigm1.len(), bigm2.len())
+}
+
+// Computes the similarity from the number of common bigrams and the numbers of
+// bigrams of each string, using the formula set by pg_bigmr.similarity_formula.
+// The score never increases as len2 grows, which the index searches rely on.
+fn similarity_score(count: usize, len1: usize, len2: usize) -> f32 {
+    if count == 0 {
+        return 0.0;
+    }
+    let (count, len1, len2) = (count as f32, len1 as f32, len2 as f32);
+    match gucs::similarity_formula() {
+        gucs::SimilarityFormula::Max => count / len1.max(len2),
+        gucs::SimilarityFormula::Union => count / (len1 + len2 - count),
+        gucs::SimilarityFormula::Dice => 2.0 * count / (len1 + len2),
+        gucs::SimilarityFormula::Overlap => count / len1.min(len2),
+    }
+}
+
+// Check whether the heap tuple fetched by index search needs to be rechecked
+// against the LIKE query. If the search word consists of one or two characters
+// and doesn't contain any space character, we can guarantee that the index test
+// would be exact. That is, the heap tuple does match the query, so it doesn't
+// need to be rechecked.
+fn like_query_needs_recheck(query: &str, bigram_list: &BigramList) -> bool {
+    !(bigram_list.bigrams.len() == 1 && !bigram_list.removed_dups && !query.contains(' '))
 }
 
+// The word similarity functions are immutable unlike bigm_similarity(), since
+// they always use the max formula regardless of pg_bigmr.similarity_formula.
 #[pg_extern(immutable, parallel_safe, strict)]
+fn bigm_word_similarity(input1: &str, input2: &str) -> f32 {
+    calc_word_similarity(input1, input2, false)
+}
+
+#[pg_extern(immutable, parallel_safe, strict)]
+fn bigm_strict_word_similarity(input1: &str, input2: &str) -> f32 {
+    calc_word_similarity(input1, input2, true)
+}
+
+// Returns the greatest similarity between the bigrams of input1 and any
+// continuous extent of the ordered bigrams of input2. If strict is true, the
+// extent must start and end at word boundaries. Like iterate_word_similarity()
+// of pg_trgm, the extents are searched in a single pass over input2, moving
+// the lower bound forward only when it increases the similarity.
+fn calc_word_similarity(input1: &str, input2: &str, strict: bool) -> f32 {
+    let bigm1 = show_bigm(input1);
+    let trimed_input2 = input2.trim();
+    if bigm1.is_empty() || trimed_input2.is_empty() {
+        return 0.0;
+    };
+
+    // Number the distinct bigrams of input2, and find the ones contained in
+    // input1.
+    let bigm2 = BigramList::ordered_from_value(trimed_input2);
+    let mut numbers = HashMap::new();
+    let indexes: Vec<usize> = bigm2
+        .iter()
+        .map(|bgm| {
+            let number = numbers.len();
+            *numbers.entry(bgm).or_insert(number)
+        })
+        .collect();
+    let mut found = vec![false; numbers.len()];
+    for (bgm, number) in numbers {
+        found[number] = bigm1.binary_search(bgm).is_ok();
+    }
+
+    // Without the strict flag, there is no point in starting or ending the
+    // extent with a bigram not contained in input1.
+    let is_lower = |i: usize| !strict || bigm2[i].starts_with(' ');
+    let is_upper = |i: usize| {
+        if strict {
+            bigm2[i].ends_with(' ')
+        } else {
+            found[indexes[i]]
+        }
+    };
+    let similarity = |count: usize, len2: usize| count as f32 / cmp::max(bigm1.len(), len2) as f32;
+
+    // The last position of each bigram in the extent, and the numbers of the
+    // distinct bigrams in it and of the ones contained in input1
+    let mut lastpos = vec![None; found.len()];
+    let mut len2 = 0;
+    let mut count = 0;
+    let mut lower = strict.then_some(0);
+    let mut max_similarity: f32 = 0.0;
+    for upper in 0..bigm2.len() {
+        check_for_interrupts!();
+
+        let index = indexes[upper];
+        if lower.is_some() || found[index] {
+            if lastpos[index].is_none() {
+                len2 += 1;
+                if found[index] {
+                    count += 1;
+                }
+            }
+            lastpos[index] = Some(upper);
+        }
+        if !is_upper(upper) {
+            continue;
+        }
+
+        // Also try to move the lower bound forward for a greater similarity.
+        // No extent from tmp_lower has a greater similarity than
+        // tmp_count / bigm1.len(), which stops the search early enough to
+        // keep the whole pass linear in the usual cases.
+        let prev_lower = *lower.get_or_insert(upper);
+        let mut cur_similarity = similarity(count, len2);
+        let (mut tmp_len2, mut tmp_count) = (len2, count);
+        for (tmp_lower, &tmp_index) in (prev_lower..).zip(&indexes[prev_lower..=upper]) {
+            if similarity(tmp_count, 0) <= cur_similarity {
+                break;
+            }
+            if is_lower(tmp_lower) {
+                let tmp_similarity = similarity(tmp_count, tmp_len2);
+                if tmp_similarity > cur_similarity {
+                    cur_similarity = tmp_similarity;
+                    lower = Some(tmp_lower);
+                    len2 = tmp_len2;
+                    count = tmp_count;
+                }
+            }
+            if lastpos[tmp_index] == Some(tmp_lower) {
+                tmp_len2 -= 1;
+                if found[tmp_index] {
+                    tmp_count -= 1;
+                }
+            }
+        }
+        max_similarity = max_similarity.max(cur_similarity);
+
+        // Forget the bigrams left behind the lower bound.
+        for (tmp_lower, &tmp_index) in (prev_lower..).zip(&indexes[prev_lower..lower.unwrap()]) {
+            if lastpos[tmp_index] == Some(tmp_lower) {
+                lastpos[tmp_index] = None;
+            }
+        }
+    }
+    max_similarity
+}
+
+// Returns the fields of the GIN metapage, starting with the size of the
+// pending list. Block numbers are int8 since they are unsigned.
+#[allow(clippy::type_complexity)]
+#[pg_extern(volatile, parallel_safe, strict)]
 fn pg_gin_pending_stats(
     index_oid: pg_sys::Oid,
-) -> TableIterator<'static, (name!(pages, i32), name!(tuples, i64))> {
-    unsafe {
-        let index_rel = PgBox::from_pg(pg_sys::relation_open(
-            index_oid,
-            pg_sys::AccessShareLock as _,
-        ));
-        let pg_class_entry = PgBox::from_pg(index_rel.rd_rel);
-
-        if pg_class_entry.relkind != pg_sys::RELKIND_INDEX as i8
-            || pg_class_entry.relam != pg_sys::GIN_AM_OID
-        {
-            pgrx::error!(
-                "relation \"{}\" is not a GIN index",
-                name_data_to_str(&pg_class_entry.relname)
-            );
-        };
+) -> TableIterator<
+    'static,
+    (
+        name!(pages, i32),
+        name!(tuples, i64),
+        name!(head, i64),
+        name!(tail, i64),
+        name!(tail_free_size, i32),
+        name!(total_pages, i64),
+        name!(entry_pages, i64),
+        name!(data_pages, i64),
+        name!(entries, i64),
+        name!(version, i32),
+    ),
+> {
+    let metadata = unsafe {
+        let index_rel = gin::open_gin_index(index_oid);
+        let metadata = gin::read_metapage(index_rel);
+        pg_sys::relation_close(index_rel, pg_sys::AccessShareLock as _);
+        metadata
+    };
 
-        // Reject attempts to read non-local temporary relations; we would be
-        // likely to get wrong data since we have no visibility into the owning
-        // session's local buffers.
-        if pg_class_entry.relpersistence == 't' as i8 && !index_rel.rd_islocaltemp {
-            pgrx::error!("cannot access temporary indexes of other sessions");
-        };
+    TableIterator::new(vec![(
+        metadata.n_pending_pages as i32,
+        metadata.n_pending_heap_tuples,
+        metadata.head.into(),
+        metadata.tail.into(),
+        metadata.tail_free_size as i32,
+        metadata.n_total_pages.into(),
+        metadata.n_entry_pages.into(),
+        metadata.n_data_pages.into(),
+        metadata.n_entries,
+        metadata.gin_version,
+    )])
+}
 
-        // Obtain statistic information from the meta page
-        let metabuffer = pg_sys::ReadBuffer(index_rel.as_ptr(), GIN_METAPAGE_BLKNO);
-        pg_sys::LockBuffer(metabuffer, GIN_SHARE);
-        let metapage = pg_sys::BufferGetPage(metabuffer) as *const u8;
+// Moves the pending list of the GIN index into its main tree, and returns the
+// numbers of the pages and the heap tuples cleaned up. The whole pending list
+// is moved, since gin_clean_pending_list() can't stop in the middle of it.
+// Only the owner of the index can do it, as gin_clean_pending_list() requires.
+#[pg_extern(volatile, strict)]
+fn pg_bigm_flush_pending(
+    index: gin::Regclass,
+) -> TableIterator<'static, (name!(pages, i32), name!(tuples, i64))> {
+    if unsafe { pg_sys::RecoveryInProgress() } {
+        pgrx::error!("pending list of GIN index cannot be flushed during recovery");
+    }
 
-        // pgrx cannot use GinPageGetMeta, so directly access the GIN meta page.
-        // Bytes 36-39 of the page indicate the number of GIN pending pages.
-        let n_pending_pages_byte = std::slice::from_raw_parts(metapage.add(36), 4);
-        let n_pending_pages = i32::from_ne_bytes(n_pending_pages_byte.try_into().unwrap());
+    let gin::Regclass(index_oid) = index;
+    let (pages, tuples) = unsafe {
+        let index_rel = gin::open_gin_index(index_oid);
+        if !is_owner_of_relation(index_oid) {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_INSUFFICIENT_PRIVILEGE,
+                format!(
+                    "must be owner of index {}",
+                    name_data_to_str(&(*(*index_rel).rd_rel).relname)
+                )
+            );
+        }
+        pg_sys::relation_close(index_rel, pg_sys::AccessShareLock as _);
+        gin::flush_pending_list(index_oid)
+    };
 
-        // Bytes 40-47 of the page indicate the number of GIN pending tuples.
-        let n_pending_tuples_byte = std::slice::from_raw_parts(metapage.add(40), 8);
-        let n_pending_tuples = i64::from_ne_bytes(n_pending_tuples_byte.try_into().unwrap());
+    TableIterator::new(vec![(pages, tuples)])
+}
 
-        pg_sys::UnlockReleaseBuffer(metabuffer);
-        pg_sys::relation_close(index_rel.as_ptr(), pg_sys::AccessShareLock as _);
+// Returns whether the current user owns the relation.
+#[cfg(any(feature = "pg12", feature = "pg13", feature = "pg14", feature = "pg15"))]
+unsafe fn is_owner_of_relation(relid: pg_sys::Oid) -> bool {
+    pg_sys::pg_class_ownercheck(relid, pg_sys::GetUserId())
+}
 
-        TableIterator::new(vec![(n_pending_pages, n_pending_tuples)])
-    }
+#[cfg(any(feature = "pg16", feature = "pg17"))]
+unsafe fn is_owner_of_relation(relid: pg_sys::Oid) -> bool {
+    pg_sys::object_ownercheck(pg_sys::RelationRelationId, relid, pg_sys::GetUserId())
 }
 
 #[pg_extern(immutable, parallel_safe, strict)]
@@ -161,9 +512,90 @@ fn bigmtextcmp(input1: &str, input2: &str) -> i32 {
     }
 }
 
+// Returns the key under which a case-folded bigram is stored in the index.
+fn casefold_key(bgm: &str) -> String {
+    format!("{CASEFOLD_KEY_PREFIX}{bgm}")
+}
+
+// Key of gin_bigm_ops
+fn text_key_datum(key: &str) -> Datum {
+    Datum::from(varlena::rust_str_to_text_p(key).into_pg())
+}
+
+// Key of gin_bigm_int_ops, which is ordered as the text key is. The sign bit
+// marks case-folded keys.
+fn int8_key(key: &str) -> i64 {
+    match key.strip_prefix(CASEFOLD_KEY_PREFIX) {
+        Some(ngram) => i64::MIN | BigramList::ngram_code(ngram),
+        None => BigramList::ngram_code(key),
+    }
+}
+
+fn int8_key_datum(key: &str) -> Datum {
+    Datum::from(int8_key(key))
+}
+
+#[pg_extern(immutable, parallel_safe, strict)]
+fn gin_extract_value_bigm(
+    item_value: &str,
+    nkeys: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Internal {
+    extract_value(item_value, nkeys, fcinfo, text_key_datum, false)
+}
+
+#[pg_extern(immutable, parallel_safe, strict)]
+fn gin_extract_value_bigm_int(
+    item_value: &str,
+    nkeys: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Internal {
+    extract_value(item_value, nkeys, fcinfo, int8_key_datum, false)
+}
+
+#[pg_extern(immutable, parallel_safe, strict)]
+fn gin_extract_value_bigm_ilike(
+    item_value: &str,
+    nkeys: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Internal {
+    extract_value(item_value, nkeys, fcinfo, text_key_datum, true)
+}
+
 #[pg_extern(immutable, parallel_safe, strict)]
-fn gin_extract_value_bigm(item_value: &str, nkeys: Internal) -> Internal {
-    let bigrams = show_bigm(item_value);
+fn gin_extract_value_bigm_int_ilike(
+    item_value: &str,
+    nkeys: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Internal {
+    extract_value(item_value, nkeys, fcinfo, int8_key_datum, true)
+}
+
+fn extract_value(
+    item_value: &str,
+    nkeys: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+    key_datum: fn(&str) -> Datum,
+    ilike: bool,
+) -> Internal {
+    let index_options = options::index_options(fcinfo);
+    let item_value = index_options.normalizer.normalize(item_value);
+    let mut bigrams = extract_ngrams(&item_value, index_options.gram_size);
+
+    // For the operator classes of ILIKE, also store the case-folded bigrams
+    // that don't appear as they are, so that ILIKE can find the value by its
+    // lower-case bigrams.
+    let trimed_value = item_value.trim();
+    if ilike && !trimed_value.is_empty() {
+        let folded_bigrams =
+            BigramList::from_value_casefold(trimed_value, index_options.gram_size).bigrams;
+        let casefold_keys: Vec<String> = folded_bigrams
+            .iter()
+            .filter(|bgm| bigrams.binary_search(bgm).is_err())
+            .map(|bgm| casefold_key(bgm))
+            .collect();
+        bigrams.extend(casefold_keys);
+    }
     let bgmlen = bigrams.len();
 
     unsafe {
@@ -171,45 +603,112 @@ fn gin_extract_value_bigm(item_value: &str, nkeys: Internal) -> Internal {
         *nkeys_ptr = bgmlen as i32;
     };
 
-    let datums = unsafe {
-        PgMemoryContexts::CurrentMemoryContext.palloc0_slice::<pg_sys::Datum>(bgmlen as usize)
-    };
+    let datums =
+        unsafe { PgMemoryContexts::CurrentMemoryContext.palloc0_slice::<pg_sys::Datum>(bgmlen) };
 
     for (i, bgm) in bigrams.iter().enumerate() {
-        let s_varlena = varlena::rust_str_to_text_p(bgm).into_pg();
-        datums[i] = Datum::from(s_varlena);
+        datums[i] = key_datum(bgm);
     }
 
     Internal::from(Some(Datum::from(datums.as_mut_ptr())))
 }
 
+#[allow(clippy::too_many_arguments)]
 #[pg_extern(immutable, parallel_safe, strict)]
 fn gin_extract_query_bigm(
-    query: &str,
+    query: Internal,
     nkeys: Internal,
     strategy_number: i16,
-    mut pmatch: Internal,
+    pmatch: Internal,
+    extra_data: Internal,
+    _null_flags: Internal,
+    search_mode: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Internal {
+    extract_query(
+        query,
+        nkeys,
+        strategy_number,
+        pmatch,
+        extra_data,
+        search_mode,
+        fcinfo,
+        text_key_datum,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+#[pg_extern(immutable, parallel_safe, strict)]
+fn gin_extract_query_bigm_int(
+    query: Internal,
+    nkeys: Internal,
+    strategy_number: i16,
+    pmatch: Internal,
     extra_data: Internal,
     _null_flags: Internal,
     search_mode: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Internal {
+    extract_query(
+        query,
+        nkeys,
+        strategy_number,
+        pmatch,
+        extra_data,
+        search_mode,
+        fcinfo,
+        int8_key_datum,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn extract_query(
+    query: Internal,
+    nkeys: Internal,
+    strategy_number: i16,
+    mut pmatch: Internal,
+    extra_data: Internal,
+    search_mode: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
+    key_datum: fn(&str) -> Datum,
 ) -> Internal {
-    let bigram_list;
+    let index_options = options::index_options(fcinfo);
+    let normalizer = &index_options.normalizer;
+    let gin_key_limit = index_options
+        .gin_key_limit
+        .unwrap_or_else(gucs::gin_key_limit);
+    let mut bigram_list;
     let bgmlen: i32;
+    // Number of keys extracted for each bigram
+    let mut keys_per_bigram = 1;
+    // Boolean expression of the keys, if not all of them are required
+    let mut bigram_expr = None;
+
+    // The query is a text except for the =% operator with bigm_similar_query,
+    // the @@ operator and the ~~| operator, so it's converted according to
+    // the strategy.
+    let query_datum = query.unwrap().unwrap();
+    let query = if matches!(
+        strategy_number,
+        SIMILAR_QUERY_STRATEGY_NUMBER | BIGM_QUERY_STRATEGY_NUMBER | LIKE_ANY_STRATEGY_NUMBER
+    ) {
+        ""
+    } else {
+        unsafe { <&str>::from_datum(query_datum, false).unwrap() }
+    };
 
     match strategy_number {
         LIKE_STRATEGY_NUMBER => {
             // For wildcard search we extract all the bigrams that every
             // potentially-matching string must include.
-            bigram_list = BigramList::from_query(query);
+            bigram_list =
+                BigramList::from_query_normalized(query, normalizer, index_options.gram_size);
             bgmlen = bigram_list.bigrams.len() as i32;
 
-            // Check whether the heap tuple fetched by index search needs to
-            // be rechecked against the query. If the search word consists of
-            // one or two characters and doesn't contain any space character,
-            // we can guarantee that the index test would be exact. That is,
-            // the heap tuple does match the query, so it doesn't need to be
-            // rechecked.
-            let needs_recheck = !(bgmlen == 1 && !bigram_list.removed_dups && !query.contains(' '));
+            // The normalized bigrams can't tell whether the value matches the
+            // query exactly.
+            let needs_recheck =
+                !normalizer.is_empty() || like_query_needs_recheck(query, &bigram_list);
             unsafe {
                 let recheck = PgMemoryContexts::CurrentMemoryContext.palloc0(mem::size_of::<bool>())
                     as *mut bool;
@@ -217,33 +716,121 @@ fn gin_extract_query_bigm(
                 *extra_data.get_mut().unwrap() = recheck as *const _;
             }
         }
-        SIMILARITY_STRATEGY_NUMBER => {
-            bigram_list = BigramList::from_value(query);
+        SIMILARITY_STRATEGY_NUMBER
+        | WORD_SIMILARITY_STRATEGY_NUMBER
+        | STRICT_WORD_SIMILARITY_STRATEGY_NUMBER => {
+            bigram_list = similarity_query_bigrams(query, &index_options);
+            bgmlen = bigram_list.bigrams.len() as i32;
+        }
+        SIMILAR_QUERY_STRATEGY_NUMBER => {
+            let similar_query = unsafe { PgHeapTuple::from_composite_datum(query_datum) };
+            let (word, threshold) = similar_query_fields(&similar_query);
+            bigram_list = similarity_query_bigrams(word.as_deref().unwrap_or(""), &index_options);
+            bgmlen = bigram_list.bigrams.len() as i32;
+
+            // Pass the threshold to the consistent functions through the
+            // extra data of every key.
+            unsafe {
+                let threshold_ptr =
+                    PgMemoryContexts::CurrentMemoryContext.leak_and_drop_on_delete(threshold);
+                let threshold_ptrs = PgMemoryContexts::CurrentMemoryContext
+                    .palloc0_slice::<*const f64>(bgmlen as usize);
+                threshold_ptrs.fill(threshold_ptr);
+                *extra_data.get_mut().unwrap() = threshold_ptrs.as_ptr();
+            }
+        }
+        ILIKE_STRATEGY_NUMBER => {
+            // Each lower-case bigram of the query can be found either as it
+            // is or as a case-folded key, so search for both of them. Only the
+            // operator classes storing the case-folded keys have this
+            // strategy.
+            let mut folded_list =
+                BigramList::from_query_casefold(query, normalizer, index_options.gram_size);
+            folded_list.bigrams = folded_list
+                .bigrams
+                .iter()
+                .flat_map(|bgm| [bgm.clone(), casefold_key(bgm)])
+                .collect();
+            bigram_list = folded_list;
             bgmlen = bigram_list.bigrams.len() as i32;
+            keys_per_bigram = 2;
+        }
+        REGEX_STRATEGY_NUMBER
+        | IREGEX_STRATEGY_NUMBER
+        | BIGM_QUERY_STRATEGY_NUMBER
+        | LIKE_ANY_STRATEGY_NUMBER => {
+            let casefold = strategy_number == IREGEX_STRATEGY_NUMBER;
+            let mut expr = match strategy_number {
+                BIGM_QUERY_STRATEGY_NUMBER => {
+                    let bigm_query = unsafe { BigmQuery::from_datum(query_datum, false).unwrap() };
+                    bigm_query.bigram_expr(normalizer, index_options.gram_size)
+                }
+                LIKE_ANY_STRATEGY_NUMBER => {
+                    let patterns =
+                        unsafe { Vec::<Option<String>>::from_datum(query_datum, false).unwrap() };
+                    like_any_expr(&patterns, normalizer, index_options.gram_size)
+                }
+                _ => regex::extract_bigrams(query, casefold, normalizer, index_options.gram_size),
+            };
+            if casefold {
+                // See the comment about ILIKE
+                expr = expr.map_leaves(&|bgm| {
+                    BigramExpr::Or(vec![
+                        BigramExpr::Leaf(bgm.clone()),
+                        BigramExpr::Leaf(casefold_key(bgm)),
+                    ])
+                });
+            }
+            let (mut expr, mut keys) = expr.index_keys();
+            if selects_rarest_keys(keys.len(), 1, gin_key_limit) {
+                let positions = sort_keys_by_rarity(&mut keys, 1, fcinfo);
+                expr = expr.map_leaves(&|i| BigramExpr::Leaf(positions[*i]));
+            }
+            bgmlen = keys.len() as i32;
+            bigram_list = BigramList {
+                bigrams: keys,
+                removed_dups: false,
+                pmatch: false,
+            };
+
+            // Pass the expression to the consistent functions through the
+            // extra data of every key.
+            unsafe {
+                let expr_ptr = PgMemoryContexts::CurrentMemoryContext.leak_and_drop_on_delete(expr);
+                let expr_ptrs = PgMemoryContexts::CurrentMemoryContext
+                    .palloc0_slice::<*const BigramExpr>(bgmlen as usize);
+                expr_ptrs.fill(expr_ptr);
+                *extra_data.get_mut().unwrap() = expr_ptrs.as_ptr();
+                bigram_expr = Some(&*expr_ptr);
+            }
         }
         _ => {
             pgrx::error!("unrecognized strategy number: {strategy_number}");
         }
     }
 
-    let nkeys_ = if gucs::gin_key_limit() == 0 {
+    if bigram_expr.is_none()
+        && selects_rarest_keys(bigram_list.bigrams.len(), keys_per_bigram, gin_key_limit)
+    {
+        sort_keys_by_rarity(&mut bigram_list.bigrams, keys_per_bigram as usize, fcinfo);
+    }
+    let nkeys_ = if gin_key_limit == 0 {
         bgmlen
     } else {
-        cmp::min(gucs::gin_key_limit(), bgmlen)
+        cmp::min(gin_key_limit * keys_per_bigram, bgmlen)
     };
     unsafe {
         let mut nkeys_ptr = PgBox::from_pg(nkeys.get_mut().unwrap() as *mut i32);
         *nkeys_ptr = nkeys_;
     };
 
-    // Convert String to varlena
+    // Convert String to the key type of the opclass
     let entries = unsafe {
         let size = mem::size_of::<pg_sys::Datum>() * bgmlen as usize;
         PgMemoryContexts::CurrentMemoryContext.palloc0_slice::<pg_sys::Datum>(size)
     };
     for (i, bgm) in bigram_list.bigrams.iter().enumerate() {
-        let s_varlena = varlena::rust_str_to_text_p(bgm).into_pg();
-        entries[i] = Datum::from(s_varlena)
+        entries[i] = key_datum(bgm)
     }
 
     // Partial match
@@ -262,8 +849,11 @@ fn gin_extract_query_bigm(
         unsafe { pmatch.insert(Internal::new(pmatch_)) };
     }
 
-    // If no bigram was extracted then we have to scan all the index.
-    if nkeys_ == 0 {
+    // If no bigram was extracted then we have to scan all the index. So do
+    // we if the expression can be satisfied without any of the keys used for
+    // the search, which happens when some keys were cut by gin_key_limit.
+    let needs_full_scan = bigram_expr.is_some_and(|expr| expr.eval(&vec![false; nkeys_ as usize]));
+    if nkeys_ == 0 || needs_full_scan {
         unsafe {
             let mut search_mode_ptr = PgBox::from_pg(search_mode.get_mut().unwrap() as *mut u32);
             *search_mode_ptr = pg_sys::GIN_SEARCH_MODE_ALL
@@ -278,12 +868,13 @@ fn gin_extract_query_bigm(
 fn gin_bigm_consistent(
     check: Internal,
     strategy_number: i16,
-    _query: &str,
+    _query: Internal,
     nkeys: i32,
     extra_data: Internal,
     recheck: Internal,
     _query_keys: Internal,
     _null_flags: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> bool {
     let check_ = unsafe { check.get().unwrap() as *const bool };
     let check_ = unsafe { std::slice::from_raw_parts(check_, nkeys as usize) };
@@ -308,7 +899,10 @@ fn gin_bigm_consistent(
             }
             true
         }
-        SIMILARITY_STRATEGY_NUMBER => {
+        SIMILARITY_STRATEGY_NUMBER
+        | WORD_SIMILARITY_STRATEGY_NUMBER
+        | STRICT_WORD_SIMILARITY_STRATEGY_NUMBER
+        | SIMILAR_QUERY_STRATEGY_NUMBER => {
             // Count the matches
             unsafe { *recheck.get_mut().unwrap() = gucs::enable_recheck() };
             let mut ntrue = 0;
@@ -318,31 +912,310 @@ fn gin_bigm_consistent(
                 };
             }
 
-            // If DIVUNION is defined then similarity formula is:
-            // c / (len1 + len2 - c)
-            // where c is number of common bigrams and it stands as ntrue in
-            // this code.  Here we don't know value of len2 but we can assume
-            // that c (ntrue) is a lower bound of len2, so upper bound of
-            // similarity is:
+            // In the similarity formulas, c is the number of common bigrams
+            // and it stands as ntrue in this code. Here we don't know value of
+            // len2 but we can assume that c (ntrue) is a lower bound of len2.
+            // Since the similarity never increases as len2 grows, its upper
+            // bound is obtained with len2 = c, e.g. for the union formula:
             // c / (len1 + c - c)  => c / len1
-            // If DIVUNION is not defined then similarity formula is:
-            // c / max(len1, len2)
-            // And again, c (ntrue) is a lower bound of len2, but c <= len1
-            // just by definition and, consequently, upper bound of
-            // similarity is just c / len1.
-            // So, independently on DIVUNION the upper bound formula is the same.
+            // and for the max formula, c <= len1 just by definition and,
+            // consequently, upper bound of similarity is also c / len1.
+            // The max formula is always used for word similarity, where c
+            // (ntrue) is also an upper bound of the number of common bigrams
+            // in any extent.
             if nkeys == 0 {
-                false
+                // See similarity_query_bigrams()
+                let rechecks_all = !options::index_options(fcinfo).has_plain_bigrams();
+                if rechecks_all {
+                    unsafe { *recheck.get_mut().unwrap() = true };
+                }
+                rechecks_all
             } else {
-                ntrue as f32 / nkeys as f32 >= gucs::similarity_limit() as f32
+                similarity_upper_bound(strategy_number, ntrue, nkeys as usize)
+                    >= similarity_limit(strategy_number, &extra_data) as f32
             }
         }
+        ILIKE_STRATEGY_NUMBER => {
+            // The lower-case bigrams of the query are only a necessary
+            // condition for ILIKE, so the heap tuple always needs to be
+            // rechecked unless pg_bigmr.enable_recheck is disabled. Nothing
+            // is known about it without any key, e.g. when the query is too
+            // short, so it's rechecked anyway.
+            unsafe { *recheck.get_mut().unwrap() = gucs::enable_recheck() || nkeys == 0 };
+
+            // Check if every bigram is presented either as it is or as a
+            // case-folded key.
+            check_.chunks(2).all(|chk| chk.iter().any(|c| *c))
+        }
+        REGEX_STRATEGY_NUMBER
+        | IREGEX_STRATEGY_NUMBER
+        | BIGM_QUERY_STRATEGY_NUMBER
+        | LIKE_ANY_STRATEGY_NUMBER => {
+            // The bigrams are only a necessary condition for the regular
+            // expression, bigm_query and the LIKE patterns, so see the
+            // comment about ILIKE.
+            unsafe { *recheck.get_mut().unwrap() = gucs::enable_recheck() || nkeys == 0 };
+
+            // Evaluate the boolean expression of the extracted bigrams.
+            nkeys == 0 || key_expr(&extra_data).eval(check_)
+        }
         _ => {
             pgrx::error!("unrecognized strategy number: {strategy_number}");
         }
     }
 }
 
+// Returns the expression of the bigrams of the ~~| operator. A matching value
+// must include all the bigrams of any of the patterns. NULL patterns never
+// match.
+fn like_any_expr(
+    patterns: &[Option<String>],
+    normalizer: &Normalizer,
+    gram_size: GramSize,
+) -> BigramExpr<String> {
+    BigramExpr::or(
+        patterns
+            .iter()
+            .flatten()
+            .map(|pattern| {
+                let bigram_list = BigramList::from_query_normalized(pattern, normalizer, gram_size);
+                BigramExpr::and(
+                    bigram_list
+                        .bigrams
+                        .into_iter()
+                        .map(BigramExpr::Leaf)
+                        .collect(),
+                )
+            })
+            .collect(),
+    )
+}
+
+// Returns the bigrams of the query of the similarity operators. Since the
+// similarity is defined on the bigrams of the texts as they are, no key is
+// extracted for the indexes of other n-grams or of normalized texts, so that
+// the whole index is scanned and rechecked.
+fn similarity_query_bigrams(query: &str, index_options: &IndexOptions) -> BigramList {
+    if index_options.has_plain_bigrams() {
+        BigramList::from_value(query)
+    } else {
+        BigramList::default()
+    }
+}
+
+// Returns whether the keys used for the search are chosen by their frequency,
+// i.e. some of them are going to be cut by gin_key_limit.
+fn selects_rarest_keys(nkeys: usize, keys_per_bigram: i32, gin_key_limit: i32) -> bool {
+    gin_key_limit > 0
+        && nkeys > (gin_key_limit * keys_per_bigram) as usize
+        && gucs::gin_key_selection() == GinKeySelection::Rarest
+}
+
+// Sorts the groups of keys extracted for each bigram from the rarest to the
+// most frequent, keeping the original order among the keys of the same
+// frequency, and returns the new position of each key. A case-folded key is
+// as frequent as its bigram, and the bigrams missing in pg_bigm_key_stats are
+// regarded as the rarest.
+fn sort_keys_by_rarity(
+    keys: &mut Vec<String>,
+    keys_per_bigram: usize,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Vec<usize> {
+    let bigrams: Vec<&str> = keys
+        .iter()
+        .map(|key| key.trim_start_matches(CASEFOLD_KEY_PREFIX))
+        .collect();
+    let frequencies = key_frequencies(&bigrams, unsafe { (*fcinfo).flinfo });
+    let group_frequency = |group: usize| -> i64 {
+        bigrams[group * keys_per_bigram..(group + 1) * keys_per_bigram]
+            .iter()
+            .map(|bgm| frequencies.get(*bgm).copied().unwrap_or(0))
+            .sum()
+    };
+    let mut groups: Vec<usize> = (0..keys.len() / keys_per_bigram).collect();
+    groups.sort_by_cached_key(|group| group_frequency(*group));
+
+    let order: Vec<usize> = groups
+        .iter()
+        .flat_map(|group| group * keys_per_bigram..(group + 1) * keys_per_bigram)
+        .collect();
+    let mut positions = vec![0; keys.len()];
+    for (new, old) in order.iter().enumerate() {
+        positions[*old] = new;
+    }
+    *keys = order.iter().map(|old| keys[*old].clone()).collect();
+    positions
+}
+
+// Looks up the frequencies of the bigrams collected by
+// pg_bigm_collect_key_stats() in the index column whose support function is
+// called with flinfo. The table is read directly through its primary key
+// rather than by a query, since this is called in the middle of the index
+// scan.
+fn key_frequencies(bigrams: &[&str], flinfo: *mut pg_sys::FmgrInfo) -> HashMap<String, i64> {
+    let mut frequencies = HashMap::new();
+    unsafe {
+        let Some(stats_oid) = key_stats_table() else {
+            return frequencies;
+        };
+        let stats_rel = pg_sys::table_open(stats_oid, pg_sys::AccessShareLock as _);
+        pg_sys::list_free(pg_sys::RelationGetIndexList(stats_rel));
+        let pkey_oid = (*stats_rel).rd_pkindex;
+        let attnum_of = |name: &CStr| {
+            NonZeroUsize::new(pg_sys::get_attnum(stats_oid, name.as_ptr()) as usize).unwrap()
+        };
+        let indexrelid_attno = attnum_of(c"indexrelid");
+        let attnum_attno = attnum_of(c"attnum");
+        let frequency_attno = attnum_of(c"frequency");
+        // Whether each column of the indexes is the one searched
+        let mut searched = HashMap::new();
+        for bgm in bigrams {
+            let mut key = pg_sys::ScanKeyData::default();
+            pg_sys::ScanKeyInit(
+                &mut key,
+                1,
+                pg_sys::BTEqualStrategyNumber as _,
+                pg_sys::Oid::from(pg_sys::F_TEXTEQ),
+                bgm.into_datum().unwrap(),
+            );
+            let scan =
+                pg_sys::systable_beginscan(stats_rel, pkey_oid, true, ptr::null_mut(), 1, &mut key);
+            loop {
+                let tuple = pg_sys::systable_getnext(scan);
+                if tuple.is_null() {
+                    break;
+                }
+                let column = |attno| heap_getattr_raw(tuple, attno, (*stats_rel).rd_att);
+                let indexrelid =
+                    pg_sys::Oid::from_datum(column(indexrelid_attno).unwrap(), false).unwrap();
+                let attnum = i16::from_datum(column(attnum_attno).unwrap(), false).unwrap();
+                if !*searched
+                    .entry((indexrelid, attnum))
+                    .or_insert_with(|| searches_index_column(flinfo, indexrelid, attnum))
+                {
+                    continue;
+                }
+                if let Some(datum) = column(frequency_attno) {
+                    *frequencies.entry(bgm.to_string()).or_default() +=
+                        i64::from_datum(datum, false).unwrap();
+                }
+            }
+            pg_sys::systable_endscan(scan);
+        }
+        pg_sys::table_close(stats_rel, pg_sys::AccessShareLock as _);
+    }
+    frequencies
+}
+
+// Returns whether flinfo is of the support function of the index column.
+// GIN doesn't tell the support functions which index is searched, but
+// index_getprocinfo() gives each index column the opclass options of its own
+// as fn_expr, which the copies of it made for the index scan share.
+#[cfg(not(feature = "pg12"))]
+unsafe fn searches_index_column(
+    flinfo: *mut pg_sys::FmgrInfo,
+    indexrelid: pg_sys::Oid,
+    attnum: i16,
+) -> bool {
+    if (*flinfo).fn_expr.is_null() {
+        return false;
+    }
+    let index = pg_sys::try_relation_open(indexrelid, pg_sys::AccessShareLock as _);
+    if index.is_null() {
+        return false;
+    }
+    let searched = (*(*index).rd_rel).relam == pg_sys::GIN_AM_OID
+        && attnum > 0
+        && attnum <= (*(*index).rd_index).indnkeyatts
+        && (*pg_sys::index_getprocinfo(index, attnum, pg_sys::GIN_EXTRACTQUERY_PROC as _)).fn_expr
+            == (*flinfo).fn_expr;
+    pg_sys::relation_close(index, pg_sys::AccessShareLock as _);
+    searched
+}
+
+// The support functions have no opclass options to tell the index column
+// before PostgreSQL 13, so the frequencies in all the indexes are summed up.
+#[cfg(feature = "pg12")]
+unsafe fn searches_index_column(
+    _flinfo: *mut pg_sys::FmgrInfo,
+    _indexrelid: pg_sys::Oid,
+    _attnum: i16,
+) -> bool {
+    true
+}
+
+// Returns the schema of pg_bigmr, which is relocatable, or None if it's not
+// installed in the database.
+pub(crate) unsafe fn extension_namespace() -> Option<pg_sys::Oid> {
+    let ext_oid = pg_sys::get_extension_oid(c"pg_bigmr".as_ptr(), true);
+    if ext_oid == pg_sys::InvalidOid {
+        return None;
+    }
+    let ext_rel = pg_sys::table_open(pg_sys::ExtensionRelationId, pg_sys::AccessShareLock as _);
+    let mut key = pg_sys::ScanKeyData::default();
+    pg_sys::ScanKeyInit(
+        &mut key,
+        pg_sys::Anum_pg_extension_oid as _,
+        pg_sys::BTEqualStrategyNumber as _,
+        pg_sys::Oid::from(pg_sys::F_OIDEQ),
+        ext_oid.into_datum().unwrap(),
+    );
+    let scan = pg_sys::systable_beginscan(
+        ext_rel,
+        pg_sys::Oid::from(pg_sys::ExtensionOidIndexId),
+        true,
+        ptr::null_mut(),
+        1,
+        &mut key,
+    );
+    let tuple = pg_sys::systable_getnext(scan);
+    let namespace = if tuple.is_null() {
+        pg_sys::InvalidOid
+    } else {
+        (*pg_sys::heap_tuple_get_struct::<pg_sys::FormData_pg_extension>(tuple)).extnamespace
+    };
+    pg_sys::systable_endscan(scan);
+    pg_sys::table_close(ext_rel, pg_sys::AccessShareLock as _);
+    (namespace != pg_sys::InvalidOid).then_some(namespace)
+}
+
+// Returns the OID of pg_bigm_key_stats in the schema of the extension, which
+// may not be in search_path.
+unsafe fn key_stats_table() -> Option<pg_sys::Oid> {
+    let namespace = extension_namespace()?;
+    let relid = pg_sys::get_relname_relid(c"pg_bigm_key_stats".as_ptr(), namespace);
+    (relid != pg_sys::InvalidOid).then_some(relid)
+}
+
+// Returns the upper bound of the similarity of the indexed value which
+// includes ntrue of the nkeys bigrams of the query.
+fn similarity_upper_bound(strategy_number: i16, ntrue: usize, nkeys: usize) -> f32 {
+    match strategy_number {
+        SIMILARITY_STRATEGY_NUMBER | SIMILAR_QUERY_STRATEGY_NUMBER => {
+            similarity_score(ntrue, nkeys, ntrue)
+        }
+        _ => ntrue as f32 / nkeys as f32,
+    }
+}
+
+// Returns the similarity threshold used by the operator of the strategy. The
+// threshold of bigm_similar_query is stored by gin_extract_query_bigm() in the
+// extra data of the keys.
+fn similarity_limit(strategy_number: i16, extra_data: &Internal) -> f64 {
+    match strategy_number {
+        SIMILAR_QUERY_STRATEGY_NUMBER => unsafe { **extra_data.get::<*const f64>().unwrap() },
+        WORD_SIMILARITY_STRATEGY_NUMBER => gucs::word_similarity_limit(),
+        STRICT_WORD_SIMILARITY_STRATEGY_NUMBER => gucs::strict_word_similarity_limit(),
+        _ => gucs::similarity_limit(),
+    }
+}
+
+// Returns the boolean expression stored by gin_extract_query_bigm() in the
+// extra data of the keys.
+fn key_expr(extra_data: &Internal) -> &BigramExpr {
+    unsafe { &**extra_data.get::<*const BigramExpr>().unwrap() }
+}
+
 #[pg_extern(immutable, parallel_safe, strict)]
 fn gin_bigm_compare_partial(
     input1: &str,
@@ -356,15 +1229,30 @@ fn gin_bigm_compare_partial(
     }
 }
 
+#[pg_extern(immutable, parallel_safe, strict)]
+fn gin_bigm_compare_partial_int(
+    input1: i64,
+    input2: i64,
+    _strategy_number: i16,
+    _extra_data: Internal,
+) -> i32 {
+    match input1 == input2 {
+        true => 0,
+        false => 1,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 #[pg_extern(immutable, parallel_safe, strict)]
 fn gin_bigm_triconsistent(
     check: Internal,
     strategy_number: i16,
-    _query: &str,
+    _query: Internal,
     nkeys: i32,
     extra_data: Internal,
     _query_keys: Internal,
     _null_flags: Internal,
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> pg_sys::GinTernaryValue {
     let check_ = unsafe { check.get().unwrap() as *const pg_sys::GinTernaryValue };
     let check_ = unsafe { std::slice::from_raw_parts(check_, nkeys as usize) };
@@ -390,7 +1278,10 @@ fn gin_bigm_triconsistent(
                 };
             }
         }
-        SIMILARITY_STRATEGY_NUMBER => {
+        SIMILARITY_STRATEGY_NUMBER
+        | WORD_SIMILARITY_STRATEGY_NUMBER
+        | STRICT_WORD_SIMILARITY_STRATEGY_NUMBER
+        | SIMILAR_QUERY_STRATEGY_NUMBER => {
             // Count the matches
             let mut ntrue = 0;
             for chk in check_.iter().take(nkeys as usize) {
@@ -401,8 +1292,14 @@ fn gin_bigm_triconsistent(
 
             // See comment in gin_bigm_consistent() about upper bound formula
             res = if nkeys == 0 {
+                // See similarity_query_bigrams()
+                if !options::index_options(fcinfo).has_plain_bigrams() {
+                    return pg_sys::GIN_MAYBE as i8;
+                }
                 pg_sys::GIN_FALSE
-            } else if ntrue as f32 / nkeys as f32 >= gucs::similarity_limit() as f32 {
+            } else if similarity_upper_bound(strategy_number, ntrue, nkeys as usize)
+                >= similarity_limit(strategy_number, &extra_data) as f32
+            {
                 pg_sys::GIN_MAYBE
             } else {
                 pg_sys::GIN_FALSE
@@ -412,6 +1309,35 @@ fn gin_bigm_triconsistent(
                 res = pg_sys::GIN_TRUE
             }
         }
+        ILIKE_STRATEGY_NUMBER => {
+            // See comment in gin_bigm_consistent() about ILIKE
+            res = if gucs::enable_recheck() || nkeys == 0 {
+                pg_sys::GIN_MAYBE
+            } else {
+                pg_sys::GIN_TRUE
+            };
+
+            if !check_
+                .chunks(2)
+                .all(|chk| chk.iter().any(|c| *c != pg_sys::GIN_FALSE as i8))
+            {
+                return pg_sys::GIN_FALSE as i8;
+            }
+        }
+        REGEX_STRATEGY_NUMBER
+        | IREGEX_STRATEGY_NUMBER
+        | BIGM_QUERY_STRATEGY_NUMBER
+        | LIKE_ANY_STRATEGY_NUMBER => {
+            // See comment in gin_bigm_consistent() about regular expressions
+            if nkeys != 0 && key_expr(&extra_data).eval_ternary(check_) == pg_sys::GIN_FALSE {
+                return pg_sys::GIN_FALSE as i8;
+            }
+            res = if gucs::enable_recheck() || nkeys == 0 {
+                pg_sys::GIN_MAYBE
+            } else {
+                pg_sys::GIN_TRUE
+            };
+        }
         _ => {
             pgrx::error!("unrecognized strategy number: {strategy_number}");
         }
@@ -0,0 +1,151 @@
+// Opclass options are supported in PostgreSQL 13 or later, so the default
+// options are always used with PostgreSQL 12.
+#[cfg(not(feature = "pg12"))]
+use std::{
+    ffi::{c_char, CStr},
+    mem,
+};
+
+use pgrx::pg_sys;
+#[cfg(not(feature = "pg12"))]
+use pgrx::{prelude::*, Internal};
+
+use crate::bigram::{GramSize, Normalizer};
+
+// Options of gin_bigm_ops given to each index, e.g.
+// CREATE INDEX ... USING gin (col gin_bigm_ops(normalize = 'nfkc', gram_size = 3))
+#[cfg(not(feature = "pg12"))]
+#[repr(C)]
+struct BigmOptions {
+    vl_len_: i32,
+    // Offset of the normalization pipeline string
+    normalize: i32,
+    gin_key_limit: i32,
+    gram_size: i32,
+    mixed_script: bool,
+    kana: bool,
+}
+
+// Options of the index whose support function is called
+#[derive(Debug, Default)]
+pub struct IndexOptions {
+    pub normalizer: Normalizer,
+    // Overrides pg_bigmr.gin_key_limit if set
+    pub gin_key_limit: Option<i32>,
+    pub gram_size: GramSize,
+}
+
+impl IndexOptions {
+    // Whether the keys are the bigrams of the texts as they are, on which the
+    // similarity is defined.
+    pub fn has_plain_bigrams(&self) -> bool {
+        self.gram_size == GramSize::default() && self.normalizer.is_empty()
+    }
+}
+
+#[cfg(not(feature = "pg12"))]
+#[pg_extern(immutable, parallel_safe)]
+fn gin_bigm_options(relopts: Internal) {
+    unsafe {
+        let relopts = relopts
+            .unwrap()
+            .unwrap()
+            .cast_mut_ptr::<pg_sys::local_relopts>();
+        pg_sys::init_local_reloptions(relopts, mem::size_of::<BigmOptions>());
+        pg_sys::add_local_string_reloption(
+            relopts,
+            c"normalize".as_ptr(),
+            c"Normalization applied to texts before bigrams are produced.".as_ptr(),
+            c"none".as_ptr(),
+            Some(validate_normalize),
+            None,
+            mem::offset_of!(BigmOptions, normalize) as i32,
+        );
+        pg_sys::add_local_int_reloption(
+            relopts,
+            c"gin_key_limit".as_ptr(),
+            c"Maximum number of bi-gram keys used for the index search, -1 means pg_bigmr.gin_key_limit.".as_ptr(),
+            -1,
+            -1,
+            i32::MAX,
+            mem::offset_of!(BigmOptions, gin_key_limit) as i32,
+        );
+        pg_sys::add_local_int_reloption(
+            relopts,
+            c"gram_size".as_ptr(),
+            c"Number of characters of each n-gram key.".as_ptr(),
+            2,
+            1,
+            3,
+            mem::offset_of!(BigmOptions, gram_size) as i32,
+        );
+        pg_sys::add_local_bool_reloption(
+            relopts,
+            c"mixed_script".as_ptr(),
+            c"Use trigram keys for ASCII characters and bigram keys for the others, instead of gram_size.".as_ptr(),
+            false,
+            mem::offset_of!(BigmOptions, mixed_script) as i32,
+        );
+        pg_sys::add_local_bool_reloption(
+            relopts,
+            c"kana".as_ptr(),
+            c"Fold katakana into hiragana after the normalization, before bigrams are produced.".as_ptr(),
+            false,
+            mem::offset_of!(BigmOptions, kana) as i32,
+        );
+    }
+}
+
+#[cfg(not(feature = "pg12"))]
+#[pg_guard]
+extern "C" fn validate_normalize(value: *const c_char) {
+    if value.is_null() {
+        return;
+    }
+    let spec = unsafe { CStr::from_ptr(value) }.to_string_lossy();
+    if let Err(msg) = Normalizer::parse(&spec) {
+        pgrx::error!("{msg}");
+    }
+}
+
+// Returns the options of the index whose support function is called with
+// fcinfo. The default options are used if the function isn't called by the
+// index, e.g. when it's called directly from SQL.
+#[cfg(not(feature = "pg12"))]
+pub fn index_options(fcinfo: pg_sys::FunctionCallInfo) -> IndexOptions {
+    unsafe {
+        let flinfo = (*fcinfo).flinfo;
+        if !pg_sys::has_fn_opclass_options(flinfo) {
+            return IndexOptions::default();
+        }
+        let options = pg_sys::get_fn_opclass_options(flinfo) as *const BigmOptions;
+        if options.is_null() {
+            return IndexOptions::default();
+        }
+
+        let mut normalizer = Normalizer::default();
+        if (*options).normalize != 0 {
+            let spec =
+                CStr::from_ptr((options as *const c_char).add((*options).normalize as usize));
+            normalizer = Normalizer::parse(&spec.to_string_lossy()).unwrap_or_default();
+        }
+        if (*options).kana {
+            normalizer = normalizer.fold_kana();
+        }
+        let gram_size = if (*options).mixed_script {
+            GramSize::MixedScript
+        } else {
+            GramSize::Fixed((*options).gram_size as usize)
+        };
+        IndexOptions {
+            normalizer,
+            gin_key_limit: Some((*options).gin_key_limit).filter(|limit| *limit >= 0),
+            gram_size,
+        }
+    }
+}
+
+#[cfg(feature = "pg12")]
+pub fn index_options(_fcinfo: pg_sys::FunctionCallInfo) -> IndexOptions {
+    IndexOptions::default()
+}
@@ -0,0 +1,283 @@
+use std::{
+    ffi::{CStr, CString},
+    fmt,
+    iter::Peekable,
+    str::Chars,
+};
+
+use pgrx::{
+    callconv::{Arg, ArgAbi, BoxRet, FcInfo},
+    pg_sys,
+    pgrx_sql_entity_graph::metadata::{
+        ArgumentError, Returns, ReturnsError, SqlMapping, SqlTranslatable,
+    },
+    prelude::*,
+};
+
+use crate::{
+    bigram::{BigramList, GramSize, Normalizer},
+    expr::BigramExpr,
+    like_pattern,
+};
+
+// The bigm_query type is created in two steps, since its input and output
+// functions need the shell type.
+extension_sql!(
+    r#"
+-- boolean combination of search words
+CREATE TYPE bigm_query;
+"#,
+    name = "bigm_query_shell",
+);
+extension_sql!(
+    r#"
+CREATE TYPE bigm_query (
+        INTERNALLENGTH = variable,
+        INPUT = bigm_query_in,
+        OUTPUT = bigm_query_out,
+        STORAGE = extended
+);
+"#,
+    name = "bigm_query",
+    requires = [bigm_query_in, bigm_query_out],
+);
+
+// Value of the bigm_query type, e.g. postgres & (index | gin) & !mysql, where
+// each word matches the texts including it.
+// Words including spaces or operators can be double-quoted, with a quote in
+// them doubled.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BigmQuery {
+    Word(String),
+    And(Vec<BigmQuery>),
+    Or(Vec<BigmQuery>),
+    Not(Box<BigmQuery>),
+}
+
+impl BigmQuery {
+    pub fn parse(input: &str) -> Option<Self> {
+        let mut parser = QueryParser {
+            chars: input.chars().peekable(),
+        };
+        let query = parser.parse_or()?;
+        parser.skip_whitespace();
+        parser.chars.peek().is_none().then_some(query)
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        match self {
+            Self::Word(word) => text.contains(word.as_str()),
+            Self::And(queries) => queries.iter().all(|query| query.matches(text)),
+            Self::Or(queries) => queries.iter().any(|query| query.matches(text)),
+            Self::Not(query) => !query.matches(text),
+        }
+    }
+
+    // Returns the expression of the bigrams that every matching text must
+    // include. Nothing can be said about the texts matching a negated query.
+    pub fn bigram_expr(&self, normalizer: &Normalizer, gram_size: GramSize) -> BigramExpr<String> {
+        match self {
+            Self::Word(word) => BigramExpr::and(
+                BigramList::from_query_normalized(&like_pattern(word), normalizer, gram_size)
+                    .bigrams
+                    .into_iter()
+                    .map(BigramExpr::Leaf)
+                    .collect(),
+            ),
+            Self::And(queries) => BigramExpr::and(
+                queries
+                    .iter()
+                    .map(|query| query.bigram_expr(normalizer, gram_size))
+                    .collect(),
+            ),
+            Self::Or(queries) => BigramExpr::or(
+                queries
+                    .iter()
+                    .map(|query| query.bigram_expr(normalizer, gram_size))
+                    .collect(),
+            ),
+            Self::Not(_) => BigramExpr::True,
+        }
+    }
+}
+
+// Writes the query with the fewest parentheses, quoting every word.
+impl fmt::Display for BigmQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (queries, separator) = match self {
+            Self::Word(word) => return write!(f, "\"{}\"", word.replace('"', "\"\"")),
+            Self::Not(query) => {
+                return match **query {
+                    Self::And(_) | Self::Or(_) => write!(f, "!({query})"),
+                    _ => write!(f, "!{query}"),
+                };
+            }
+            Self::And(queries) => (queries, " & "),
+            Self::Or(queries) => (queries, " | "),
+        };
+        for (i, query) in queries.iter().enumerate() {
+            if i > 0 {
+                f.write_str(separator)?;
+            }
+            match (self, query) {
+                (Self::And(_), Self::Or(_)) => write!(f, "({query})")?,
+                _ => write!(f, "{query}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+struct QueryParser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl QueryParser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    // Consumes the operator if it's the next character.
+    fn eat(&mut self, op: char) -> bool {
+        self.skip_whitespace();
+        self.chars.next_if_eq(&op).is_some()
+    }
+
+    fn parse_or(&mut self) -> Option<BigmQuery> {
+        let mut queries = Vec::new();
+        loop {
+            // Flatten the nested ORs, e.g. (a | b) | c.
+            match self.parse_and()? {
+                BigmQuery::Or(inner) => queries.extend(inner),
+                query => queries.push(query),
+            }
+            if !self.eat('|') {
+                break;
+            }
+        }
+        Some(match queries.len() {
+            1 => queries.pop().unwrap(),
+            _ => BigmQuery::Or(queries),
+        })
+    }
+
+    fn parse_and(&mut self) -> Option<BigmQuery> {
+        let mut queries = Vec::new();
+        loop {
+            match self.parse_not()? {
+                BigmQuery::And(inner) => queries.extend(inner),
+                query => queries.push(query),
+            }
+            if !self.eat('&') {
+                break;
+            }
+        }
+        Some(match queries.len() {
+            1 => queries.pop().unwrap(),
+            _ => BigmQuery::And(queries),
+        })
+    }
+
+    fn parse_not(&mut self) -> Option<BigmQuery> {
+        if self.eat('!') {
+            return Some(BigmQuery::Not(Box::new(self.parse_not()?)));
+        }
+        if self.eat('(') {
+            let query = self.parse_or()?;
+            return self.eat(')').then_some(query);
+        }
+        self.parse_word()
+    }
+
+    fn parse_word(&mut self) -> Option<BigmQuery> {
+        self.skip_whitespace();
+        let mut word = String::new();
+        if self.chars.next_if_eq(&'"').is_some() {
+            loop {
+                match self.chars.next()? {
+                    '"' if self.chars.next_if_eq(&'"').is_none() => break,
+                    c => word.push(c),
+                }
+            }
+        } else {
+            while let Some(c) = self
+                .chars
+                .next_if(|c| !c.is_whitespace() && !"&|!()\"".contains(*c))
+            {
+                word.push(c);
+            }
+        }
+        (!word.is_empty()).then_some(BigmQuery::Word(word))
+    }
+}
+
+// The value is stored as its text representation.
+impl FromDatum for BigmQuery {
+    unsafe fn from_polymorphic_datum(
+        datum: pg_sys::Datum,
+        is_null: bool,
+        _typoid: pg_sys::Oid,
+    ) -> Option<Self> {
+        let text = unsafe { <&str>::from_datum(datum, is_null)? };
+        Some(Self::parse(text).unwrap_or_else(|| pgrx::error!("invalid bigm_query: \"{text}\"")))
+    }
+}
+
+impl IntoDatum for BigmQuery {
+    fn into_datum(self) -> Option<pg_sys::Datum> {
+        self.to_string().into_datum()
+    }
+
+    fn type_oid() -> pg_sys::Oid {
+        pgrx::wrappers::regtypein("bigm_query")
+    }
+}
+
+unsafe impl<'fcx> ArgAbi<'fcx> for BigmQuery {
+    unsafe fn unbox_arg_unchecked(arg: Arg<'_, 'fcx>) -> Self {
+        let index = arg.index();
+        unsafe {
+            arg.unbox_arg_using_from_datum()
+                .unwrap_or_else(|| panic!("argument {index} must not be null"))
+        }
+    }
+}
+
+unsafe impl BoxRet for BigmQuery {
+    unsafe fn box_into<'fcx>(self, fcinfo: &mut FcInfo<'fcx>) -> pgrx::datum::Datum<'fcx> {
+        match self.into_datum() {
+            Some(datum) => unsafe { fcinfo.return_raw_datum(datum) },
+            None => fcinfo.return_null(),
+        }
+    }
+}
+
+unsafe impl SqlTranslatable for BigmQuery {
+    fn argument_sql() -> Result<SqlMapping, ArgumentError> {
+        Ok(SqlMapping::As(String::from("bigm_query")))
+    }
+
+    fn return_sql() -> Result<Returns, ReturnsError> {
+        Ok(Returns::One(SqlMapping::As(String::from("bigm_query"))))
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, strict, requires = ["bigm_query_shell"])]
+fn bigm_query_in(input: &CStr) -> BigmQuery {
+    let input = input.to_string_lossy();
+    BigmQuery::parse(&input)
+        .unwrap_or_else(|| pgrx::error!("syntax error in bigm_query: \"{input}\""))
+}
+
+#[pg_extern(immutable, parallel_safe, strict, requires = ["bigm_query_shell"])]
+fn bigm_query_out(input: BigmQuery) -> CString {
+    CString::new(input.to_string()).unwrap()
+}
+
+#[pg_operator(immutable, parallel_safe, strict, requires = [bigm_sel, "bigm_query"])]
+#[opname(@@)]
+#[restrict(bigm_sel)]
+#[join(contjoinsel)]
+fn bigm_query_match(input: &str, query: BigmQuery) -> bool {
+    query.matches(input)
+}
@@ -0,0 +1,255 @@
+use crate::bigram::{lowercase, BigramList, GramSize, Normalizer};
+use crate::expr::BigramExpr;
+
+// Extracts the bigrams that every string matching the regular expression must
+// include, as a boolean expression. Literal runs give bigrams that must all be
+// present, and alternations give groups of which any one must be present.
+// Returns BigramExpr::True when nothing can be said about the matching strings,
+// e.g. for the regular expressions using syntax that isn't understood here.
+// The normalization is applied to each literal run, and n-grams of gram_size
+// are extracted from it.
+pub fn extract_bigrams(
+    pattern: &str,
+    casefold: bool,
+    normalizer: &Normalizer,
+    gram_size: GramSize,
+) -> BigramExpr<String> {
+    // Directors and embedded options can change the meaning of the whole
+    // regular expression, so give up on them.
+    if pattern.starts_with("***") || pattern.starts_with("(?") && !pattern.starts_with("(?:") {
+        return BigramExpr::True;
+    }
+
+    let mut parser = RegexParser {
+        chars: pattern.chars().collect(),
+        pos: 0,
+        casefold,
+        normalizer,
+        gram_size,
+    };
+    match parser.parse_alternation() {
+        Some(expr) if parser.pos == parser.chars.len() => expr,
+        _ => BigramExpr::True,
+    }
+}
+
+struct RegexParser<'a> {
+    chars: Vec<char>,
+    pos: usize,
+    casefold: bool,
+    normalizer: &'a Normalizer,
+    gram_size: GramSize,
+}
+
+// How many times the preceding atom may appear
+enum Quantifier {
+    One,
+    ZeroOrMore,
+    OneOrMore,
+}
+
+impl RegexParser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.pos += 1;
+        c
+    }
+
+    fn parse_alternation(&mut self) -> Option<BigramExpr<String>> {
+        let mut branches = vec![self.parse_sequence()?];
+        while self.peek() == Some('|') {
+            self.next();
+            branches.push(self.parse_sequence()?);
+        }
+        Some(BigramExpr::or(branches))
+    }
+
+    fn parse_sequence(&mut self) -> Option<BigramExpr<String>> {
+        let mut parts = Vec::new();
+        let mut run = String::new();
+
+        while let Some(c) = self.peek() {
+            match c {
+                '|' | ')' => break,
+                '(' => {
+                    self.next();
+                    parts.push(self.make_bigrams(&mut run));
+
+                    // Only non-capturing groups are supported among the
+                    // special groups.
+                    if self.peek() == Some('?') {
+                        self.next();
+                        if self.next() != Some(':') {
+                            return None;
+                        }
+                    }
+                    let group = self.parse_alternation()?;
+                    if self.next() != Some(')') {
+                        return None;
+                    }
+                    if let Quantifier::One | Quantifier::OneOrMore = self.parse_quantifier()? {
+                        parts.push(group);
+                    }
+                }
+                '[' => {
+                    self.next();
+                    parts.push(self.make_bigrams(&mut run));
+                    self.skip_bracket()?;
+                    self.parse_quantifier()?;
+                }
+                '.' => {
+                    self.next();
+                    parts.push(self.make_bigrams(&mut run));
+                    self.parse_quantifier()?;
+                }
+                '^' | '$' => {
+                    self.next();
+                    parts.push(self.make_bigrams(&mut run));
+                }
+                '*' | '+' | '?' | '{' => return None,
+                '\\' => {
+                    self.next();
+                    let e = self.next()?;
+                    if !e.is_alphanumeric() {
+                        self.push_literal(&mut run, &mut parts, e)?;
+                    } else if "dDsSwW".contains(e) {
+                        // class-shorthand escapes match a single character
+                        parts.push(self.make_bigrams(&mut run));
+                        self.parse_quantifier()?;
+                    } else if "AZmMyY".contains(e) {
+                        // constraint escapes match no character
+                        parts.push(self.make_bigrams(&mut run));
+                    } else {
+                        // back references and character-entry escapes
+                        return None;
+                    }
+                }
+                _ => {
+                    self.next();
+                    self.push_literal(&mut run, &mut parts, c)?;
+                }
+            }
+        }
+        parts.push(self.make_bigrams(&mut run));
+        Some(BigramExpr::and(parts))
+    }
+
+    fn push_literal(
+        &mut self,
+        run: &mut String,
+        parts: &mut Vec<BigramExpr<String>>,
+        c: char,
+    ) -> Option<()> {
+        match self.parse_quantifier()? {
+            Quantifier::One => run.push(c),
+            Quantifier::ZeroOrMore => parts.push(self.make_bigrams(run)),
+            Quantifier::OneOrMore => {
+                // The character is required, but it may be repeated, so it
+                // can only be combined with its neighbours on each side.
+                run.push(c);
+                parts.push(self.make_bigrams(run));
+                run.push(c);
+            }
+        }
+        Some(())
+    }
+
+    fn parse_quantifier(&mut self) -> Option<Quantifier> {
+        let quantifier = match self.peek() {
+            Some('*') | Some('?') => {
+                self.next();
+                Quantifier::ZeroOrMore
+            }
+            Some('+') => {
+                self.next();
+                Quantifier::OneOrMore
+            }
+            Some('{') => {
+                self.next();
+                let min = self.parse_bound()?;
+                let max = if self.peek() == Some(',') {
+                    self.next();
+                    self.parse_bound()
+                } else {
+                    Some(min)
+                };
+                if self.next() != Some('}') {
+                    return None;
+                }
+                match (min, max) {
+                    (0, _) => Quantifier::ZeroOrMore,
+                    (1, Some(1)) => Quantifier::One,
+                    _ => Quantifier::OneOrMore,
+                }
+            }
+            _ => return Some(Quantifier::One),
+        };
+
+        // Skip the non-greedy marker.
+        if self.peek() == Some('?') {
+            self.next();
+        }
+        Some(quantifier)
+    }
+
+    fn parse_bound(&mut self) -> Option<u32> {
+        let mut bound = String::new();
+        while let Some(c) = self.peek().filter(|c| c.is_ascii_digit()) {
+            self.next();
+            bound.push(c);
+        }
+        bound.parse().ok()
+    }
+
+    // Skips a bracket expression, whose opening bracket is already consumed.
+    fn skip_bracket(&mut self) -> Option<()> {
+        if self.peek() == Some('^') {
+            self.next();
+        }
+        // A leading closing bracket is taken as a literal.
+        if self.peek() == Some(']') {
+            self.next();
+        }
+        loop {
+            match self.next()? {
+                ']' => return Some(()),
+                '[' if matches!(self.peek(), Some(':') | Some('.') | Some('=')) => {
+                    // Skip the character class, collating element or
+                    // equivalence class such as [:alpha:].
+                    let delim = self.next()?;
+                    while !(self.next()? == delim && self.peek() == Some(']')) {}
+                    self.next();
+                }
+                '\\' => {
+                    self.next()?;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Converts the literal run to the bigrams which must all be present, and
+    // starts a new run. Bigrams containing white spaces are skipped since they
+    // may come from the spaces removed from the indexed value.
+    fn make_bigrams(&self, run: &mut String) -> BigramExpr<String> {
+        let literal = self.normalizer.normalize_part(run, true, true);
+        let literal = if self.casefold {
+            lowercase(&literal)
+        } else {
+            literal.into_owned()
+        };
+        run.clear();
+
+        BigramExpr::and(
+            BigramList::from_literal(&literal, self.gram_size)
+                .into_iter()
+                .filter(|ngram| !ngram.chars().any(char::is_whitespace))
+                .map(BigramExpr::Leaf)
+                .collect(),
+        )
+    }
+}
@@ -0,0 +1,542 @@
+use std::{
+    collections::HashMap,
+    ffi::{c_void, CStr},
+    mem, ptr, slice,
+};
+
+use pgrx::{list::List, memcx, pg_sys, prelude::*, Internal};
+
+use crate::{
+    bigm::BigmVector,
+    bigram::{BigramList, GramSize, Normalizer},
+    expr::BigramExpr,
+    extension_namespace, gucs, like_any_expr,
+    query::BigmQuery,
+    similarity_score, BIGM_QUERY_STRATEGY_NUMBER, LIKE_ANY_STRATEGY_NUMBER,
+    SIMILARITY_STRATEGY_NUMBER,
+};
+
+// Selectivity guessed without statistics, same as contsel()
+const DEFAULT_BIGM_SEL: f64 = 0.001;
+
+// Restriction selectivity estimator of the operators of pg_bigmr. The values
+// sampled by ANALYZE, i.e. the most common values and the histogram bounds of
+// the column, are used as follows:
+// - @@ and ~~|: the frequencies of the bigrams in the sample tell how many
+//   rows include the bigrams that a matching row must include.
+// - the other operators like =%: the operator is applied to the sample.
+// The statistics of bigm columns are the most common bigrams collected by
+// bigm_typanalyze() instead, which are also used for =%, @@ and ~~| on a text
+// column if it has an index on to_bigm() of it. LIKE is pg_catalog.~~, whose
+// estimator can't be replaced by an extension, and gin_key_selection uses the
+// frequencies in pg_bigm_key_stats, so they don't use the most common bigrams.
+#[pg_extern(stable, parallel_safe, strict)]
+fn bigm_sel(
+    root: Internal,
+    operator: pg_sys::Oid,
+    args: Internal,
+    var_relid: i32,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> f64 {
+    unsafe {
+        let root = root.unwrap().unwrap().cast_mut_ptr::<pg_sys::PlannerInfo>();
+        let args = args.unwrap().unwrap().cast_mut_ptr::<pg_sys::List>();
+        let mut vardata = pg_sys::VariableStatData::default();
+        let mut other = ptr::null_mut();
+        let mut varonleft = false;
+        if !pg_sys::get_restriction_variable(
+            root,
+            args,
+            var_relid,
+            &mut vardata,
+            &mut other,
+            &mut varonleft,
+        ) {
+            return DEFAULT_BIGM_SEL;
+        }
+
+        let selec =
+            restriction_selectivity(&vardata, other, varonleft, operator, (*fcinfo).fncollation);
+
+        // ReleaseVariableStats()
+        if !vardata.statsTuple.is_null() {
+            if let Some(freefunc) = vardata.freefunc {
+                freefunc(vardata.statsTuple);
+            }
+        }
+        selec
+    }
+}
+
+unsafe fn restriction_selectivity(
+    vardata: &pg_sys::VariableStatData,
+    other: *mut pg_sys::Node,
+    varonleft: bool,
+    operator: pg_sys::Oid,
+    collation: pg_sys::Oid,
+) -> f64 {
+    if !pgrx::is_a(other, pg_sys::NodeTag::T_Const) {
+        return DEFAULT_BIGM_SEL;
+    }
+    let constant = other as *mut pg_sys::Const;
+    // The operators are strict.
+    if (*constant).constisnull {
+        return 0.0;
+    }
+    let value = (*constant).constvalue;
+    let procedure = pg_sys::get_opcode(operator);
+    let Some(namespace) = extension_namespace() else {
+        return DEFAULT_BIGM_SEL;
+    };
+    let bigm_operator = BigmOperator::of(operator, namespace);
+
+    // The statistics of bigm columns have no sampled values but the most
+    // common bigrams.
+    if bigm_operator == BigmOperator::VectorSimilarity {
+        let query = BigmVector::from_datum(value, false).unwrap();
+        return match MostCommonBigrams::new(vardata.statsTuple) {
+            Some(mcb) => mcb.similarity_selectivity(&query.bigrams().collect::<Vec<_>>()),
+            None => DEFAULT_BIGM_SEL,
+        };
+    }
+
+    // The most common bigrams of a text column are the ones of the index on
+    // to_bigm() of it.
+    if varonleft {
+        if let Some(mcb) = MostCommonBigrams::of_bigm_index(vardata, namespace) {
+            match bigm_operator {
+                BigmOperator::Similarity => {
+                    let query = <&str>::from_datum(value, false).unwrap();
+                    return mcb.similarity_selectivity(&BigramList::from_value(query).bigrams);
+                }
+                BigmOperator::QueryMatch => {
+                    let query = BigmQuery::from_datum(value, false).unwrap();
+                    return mcb
+                        .estimate(&query.bigram_expr(&Normalizer::default(), GramSize::default()));
+                }
+                BigmOperator::LikeAny => {
+                    let patterns = Vec::<Option<String>>::from_datum(value, false).unwrap();
+                    return mcb.estimate(&like_any_expr(
+                        &patterns,
+                        &Normalizer::default(),
+                        GramSize::default(),
+                    ));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let Some(sample) = ColumnSample::new(vardata, procedure) else {
+        return DEFAULT_BIGM_SEL;
+    };
+    match bigm_operator {
+        BigmOperator::QueryMatch if varonleft => {
+            let query = BigmQuery::from_datum(value, false).unwrap();
+            sample.estimate(&query.bigram_expr(&Normalizer::default(), GramSize::default()))
+        }
+        BigmOperator::LikeAny if varonleft => {
+            let patterns = Vec::<Option<String>>::from_datum(value, false).unwrap();
+            sample.estimate(&like_any_expr(
+                &patterns,
+                &Normalizer::default(),
+                GramSize::default(),
+            ))
+        }
+        _ => sample.apply(procedure, collation, value, varonleft),
+    }
+}
+
+// Operators of pg_bigmr estimated with the bigrams of the query, told by their
+// strategy numbers in the operator families of pg_bigmr
+#[derive(Clone, Copy, PartialEq)]
+enum BigmOperator {
+    // =% of bigm
+    VectorSimilarity,
+    // =% of text
+    Similarity,
+    // @@
+    QueryMatch,
+    // ~~|
+    LikeAny,
+    Other,
+}
+
+impl BigmOperator {
+    unsafe fn of(operator: pg_sys::Oid, namespace: pg_sys::Oid) -> Self {
+        let strategy = |opfamily: &CStr| {
+            let opfamily = pg_sys::GetSysCacheOid(
+                pg_sys::SysCacheIdentifier::OPFAMILYAMNAMENSP as _,
+                pg_sys::Anum_pg_opfamily_oid as _,
+                pg_sys::GIN_AM_OID.into_datum().unwrap(),
+                pg_sys::Datum::from(opfamily.as_ptr()),
+                namespace.into_datum().unwrap(),
+                pg_sys::Datum::from(0),
+            );
+            if opfamily == pg_sys::InvalidOid {
+                return 0;
+            }
+            pg_sys::get_op_opfamily_strategy(operator, opfamily) as i16
+        };
+        if strategy(c"bigm_ops") == SIMILARITY_STRATEGY_NUMBER {
+            return Self::VectorSimilarity;
+        }
+        match strategy(c"gin_bigm_ops") {
+            SIMILARITY_STRATEGY_NUMBER => Self::Similarity,
+            BIGM_QUERY_STRATEGY_NUMBER => Self::QueryMatch,
+            LIKE_ANY_STRATEGY_NUMBER => Self::LikeAny,
+            _ => Self::Other,
+        }
+    }
+}
+
+// Values sampled in the statistics of a column, each with the fraction of the
+// rows it stands for
+struct ColumnSample {
+    slots: Vec<pg_sys::AttStatsSlot>,
+    values: Vec<(pg_sys::Datum, f64)>,
+}
+
+impl ColumnSample {
+    // Reads the sampled values, if the user may see them or the procedure of
+    // the operator applied to them is leakproof, like mcv_selectivity() does.
+    unsafe fn new(vardata: &pg_sys::VariableStatData, procedure: pg_sys::Oid) -> Option<Self> {
+        if vardata.statsTuple.is_null()
+            || !pg_sys::statistic_proc_security_check(
+                vardata as *const _ as *mut pg_sys::VariableStatData,
+                procedure,
+            )
+        {
+            return None;
+        }
+        let stats =
+            pg_sys::heap_tuple_get_struct::<pg_sys::FormData_pg_statistic>(vardata.statsTuple);
+        let mut sample = Self {
+            slots: Vec::new(),
+            values: Vec::new(),
+        };
+
+        // Each of the most common values stands for its frequency.
+        let mut mcv = pg_sys::AttStatsSlot::default();
+        if pg_sys::get_attstatsslot(
+            &mut mcv,
+            vardata.statsTuple,
+            pg_sys::STATISTIC_KIND_MCV as i32,
+            pg_sys::InvalidOid,
+            (pg_sys::ATTSTATSSLOT_VALUES | pg_sys::ATTSTATSSLOT_NUMBERS) as i32,
+        ) {
+            let values = slice::from_raw_parts(mcv.values, mcv.nvalues as usize);
+            let numbers = slice::from_raw_parts(mcv.numbers, mcv.nnumbers as usize);
+            sample
+                .values
+                .extend(values.iter().zip(numbers).map(|(v, n)| (*v, *n as f64)));
+            sample.slots.push(mcv);
+        }
+
+        // The histogram bounds share the rest of the non-null rows.
+        let mut hist = pg_sys::AttStatsSlot::default();
+        if pg_sys::get_attstatsslot(
+            &mut hist,
+            vardata.statsTuple,
+            pg_sys::STATISTIC_KIND_HISTOGRAM as i32,
+            pg_sys::InvalidOid,
+            pg_sys::ATTSTATSSLOT_VALUES as i32,
+        ) {
+            let values = slice::from_raw_parts(hist.values, hist.nvalues as usize);
+            let common: f64 = sample.values.iter().map(|(_, freq)| freq).sum();
+            let rest = (1.0 - (*stats).stanullfrac as f64 - common).max(0.0);
+            let freq = rest / values.len().max(1) as f64;
+            sample.values.extend(values.iter().map(|v| (*v, freq)));
+            sample.slots.push(hist);
+        }
+
+        (!sample.values.is_empty()).then_some(sample)
+    }
+
+    // Frequency assumed for what no sampled value satisfies, i.e. rarer than
+    // any of them
+    fn unseen_frequency(&self) -> f64 {
+        let min = self
+            .values
+            .iter()
+            .map(|(_, freq)| *freq)
+            .filter(|freq| *freq > 0.0)
+            .fold(DEFAULT_BIGM_SEL * 2.0, f64::min);
+        min / 2.0
+    }
+
+    // Estimates the selectivity of the expression of the bigrams from the
+    // fraction of the rows including each bigram. A key of a single
+    // character matches the bigrams starting with it like the partial match.
+    unsafe fn estimate(&self, expr: &BigramExpr<String>) -> f64 {
+        let bigram_lists: Vec<(Vec<String>, f64)> = self
+            .values
+            .iter()
+            .map(|(value, freq)| {
+                let value = <&str>::from_datum(*value, false).unwrap();
+                (BigramList::from_value(value).bigrams, *freq)
+            })
+            .collect();
+        let unseen = self.unseen_frequency();
+        expr.estimate(&|key: &String| {
+            let freq: f64 = bigram_lists
+                .iter()
+                .filter(|(bigrams, _)| bigrams.iter().any(|bgm| bgm.starts_with(key.as_str())))
+                .map(|(_, freq)| freq)
+                .sum();
+            if freq > 0.0 {
+                freq
+            } else {
+                unseen
+            }
+        })
+    }
+
+    // Estimates the selectivity of the operator by applying its procedure to
+    // the sampled values.
+    unsafe fn apply(
+        &self,
+        procedure: pg_sys::Oid,
+        collation: pg_sys::Oid,
+        value: pg_sys::Datum,
+        varonleft: bool,
+    ) -> f64 {
+        let mut flinfo = pg_sys::FmgrInfo::default();
+        pg_sys::fmgr_info(procedure, &mut flinfo);
+        let selec: f64 = self
+            .values
+            .iter()
+            .filter(|(sample, _)| {
+                let args = if varonleft {
+                    [*sample, value]
+                } else {
+                    [value, *sample]
+                };
+                call_operator(&mut flinfo, collation, args) == Some(true)
+            })
+            .map(|(_, freq)| freq)
+            .sum();
+        if selec > 0.0 {
+            selec
+        } else {
+            self.unseen_frequency()
+        }
+    }
+}
+
+impl Drop for ColumnSample {
+    fn drop(&mut self) {
+        for slot in &mut self.slots {
+            unsafe { pg_sys::free_attstatsslot(slot) };
+        }
+    }
+}
+
+// Most common bigrams collected by bigm_typanalyze(), with the fraction of the
+// non-null rows including each of them
+struct MostCommonBigrams {
+    frequencies: HashMap<String, f64>,
+    min_frequency: f64,
+    nullfrac: f64,
+}
+
+impl MostCommonBigrams {
+    unsafe fn new(stats_tuple: pg_sys::HeapTuple) -> Option<Self> {
+        if stats_tuple.is_null() {
+            return None;
+        }
+        let stats = pg_sys::heap_tuple_get_struct::<pg_sys::FormData_pg_statistic>(stats_tuple);
+        let mut slot = pg_sys::AttStatsSlot::default();
+        if !pg_sys::get_attstatsslot(
+            &mut slot,
+            stats_tuple,
+            pg_sys::STATISTIC_KIND_MCELEM as i32,
+            pg_sys::InvalidOid,
+            (pg_sys::ATTSTATSSLOT_VALUES | pg_sys::ATTSTATSSLOT_NUMBERS) as i32,
+        ) {
+            return None;
+        }
+
+        let values = slice::from_raw_parts(slot.values, slot.nvalues as usize);
+        let numbers = slice::from_raw_parts(slot.numbers, slot.nnumbers as usize);
+        let mcb = Self {
+            frequencies: values
+                .iter()
+                .zip(numbers)
+                .map(|(v, n)| (String::from_datum(*v, false).unwrap(), *n as f64))
+                .collect(),
+            // The frequencies are followed by the minimum one.
+            min_frequency: numbers
+                .get(values.len())
+                .map_or(DEFAULT_BIGM_SEL, |n| *n as f64),
+            nullfrac: (*stats).stanullfrac as f64,
+        };
+        pg_sys::free_attstatsslot(&mut slot);
+        Some(mcb)
+    }
+
+    // Reads the statistics of the index column to_bigm(col) of the column, if
+    // the statistics of the column can be seen by the user.
+    unsafe fn of_bigm_index(
+        vardata: &pg_sys::VariableStatData,
+        namespace: pg_sys::Oid,
+    ) -> Option<Self> {
+        let var = pg_sys::strip_implicit_coercions(vardata.var);
+        if vardata.rel.is_null() || !vardata.acl_ok || !pgrx::is_a(var, pg_sys::NodeTag::T_Var) {
+            return None;
+        }
+        let var = var as *mut pg_sys::Var;
+        let argtypes = pg_sys::buildoidvector(&pg_sys::TEXTOID, 1);
+        let to_bigm = pg_sys::GetSysCacheOid(
+            pg_sys::SysCacheIdentifier::PROCNAMEARGSNSP as _,
+            pg_sys::Anum_pg_proc_oid as _,
+            pg_sys::Datum::from(c"to_bigm".as_ptr()),
+            pg_sys::Datum::from(argtypes),
+            namespace.into_datum().unwrap(),
+            pg_sys::Datum::from(0),
+        );
+
+        for index in list_ptrs::<pg_sys::IndexOptInfo>((*vardata.rel).indexlist) {
+            let mut exprs = list_ptrs::<pg_sys::Node>((*index).indexprs).into_iter();
+            let indexkeys = slice::from_raw_parts((*index).indexkeys, (*index).ncolumns as usize);
+            for (i, indexkey) in indexkeys.iter().enumerate() {
+                // The expressions are for the columns without any table column.
+                if *indexkey != 0 {
+                    continue;
+                }
+                let Some(expr) = exprs.next() else {
+                    break;
+                };
+                if !is_to_bigm_of(expr, var, to_bigm) {
+                    continue;
+                }
+                let stats_tuple = pg_sys::SearchSysCache3(
+                    pg_sys::SysCacheIdentifier::STATRELATTINH as _,
+                    (*index).indexoid.into_datum().unwrap(),
+                    (i as i16 + 1).into_datum().unwrap(),
+                    false.into_datum().unwrap(),
+                );
+                if stats_tuple.is_null() {
+                    continue;
+                }
+                let mcb = Self::new(stats_tuple);
+                pg_sys::ReleaseSysCache(stats_tuple);
+                if mcb.is_some() {
+                    return mcb;
+                }
+            }
+        }
+        None
+    }
+
+    // Estimates the selectivity of the expression of the bigrams like
+    // ColumnSample::estimate(). A key of a single character is as frequent as
+    // the most common bigram starting with it.
+    fn estimate(&self, expr: &BigramExpr<String>) -> f64 {
+        let unseen = self.min_frequency / 2.0;
+        let selec = expr.estimate(&|key: &String| {
+            if key.chars().count() == 1 {
+                self.frequencies
+                    .iter()
+                    .filter(|(bgm, _)| bgm.starts_with(key.as_str()))
+                    .map(|(_, freq)| *freq)
+                    .fold(unseen, f64::max)
+            } else {
+                self.frequencies.get(key).copied().unwrap_or(unseen)
+            }
+        });
+        selec * (1.0 - self.nullfrac)
+    }
+
+    // Estimates the selectivity of =% as the probability that a row includes
+    // enough bigrams of the query, supposing that the row has as many bigrams
+    // as the query and includes each of them independently. The bigrams
+    // missing in the statistics are rarer than the most common ones.
+    fn similarity_selectivity(&self, bigrams: &[String]) -> f64 {
+        let unseen = self.min_frequency / 2.0;
+        let nbigrams = bigrams.len();
+        let limit = gucs::similarity_limit() as f32;
+        let Some(min_count) =
+            (0..=nbigrams).find(|count| similarity_score(*count, nbigrams, nbigrams) >= limit)
+        else {
+            return unseen;
+        };
+
+        // Distribution of the number of the bigrams of the query in a row
+        let mut dist = vec![0.0; nbigrams + 1];
+        dist[0] = 1.0;
+        for bgm in bigrams {
+            let p = self.frequencies.get(bgm).copied().unwrap_or(unseen);
+            for count in (1..=nbigrams).rev() {
+                dist[count] = dist[count] * (1.0 - p) + dist[count - 1] * p;
+            }
+            dist[0] *= 1.0 - p;
+        }
+        let selec: f64 = dist[min_count..].iter().sum();
+        (selec * (1.0 - self.nullfrac)).max(unseen)
+    }
+}
+
+// Returns whether the expression is to_bigm() of the column, given the OID of
+// to_bigm().
+unsafe fn is_to_bigm_of(
+    expr: *mut pg_sys::Node,
+    var: *mut pg_sys::Var,
+    to_bigm: pg_sys::Oid,
+) -> bool {
+    if !pgrx::is_a(expr, pg_sys::NodeTag::T_FuncExpr) {
+        return false;
+    }
+    let func = expr as *mut pg_sys::FuncExpr;
+    if (*func).funcid != to_bigm {
+        return false;
+    }
+    let [arg] = list_ptrs::<pg_sys::Node>((*func).args)[..] else {
+        return false;
+    };
+    let arg = pg_sys::strip_implicit_coercions(arg);
+    pgrx::is_a(arg, pg_sys::NodeTag::T_Var)
+        && (*(arg as *mut pg_sys::Var)).varno == (*var).varno
+        && (*(arg as *mut pg_sys::Var)).varattno == (*var).varattno
+}
+
+// Returns the pointers in the list.
+unsafe fn list_ptrs<T>(list: *mut pg_sys::List) -> Vec<*mut T> {
+    memcx::current_context(|mcx| {
+        List::<*mut c_void>::downcast_ptr_in_memcx(list, mcx)
+            .map_or_else(Vec::new, |list| list.iter().map(|ptr| ptr.cast()).collect())
+    })
+}
+
+// Same as FunctionCall2Coll() except that NULL is returned rather than raising
+// an error, e.g. for bigm_similar_query without the search word.
+unsafe fn call_operator(
+    flinfo: &mut pg_sys::FmgrInfo,
+    collation: pg_sys::Oid,
+    args: [pg_sys::Datum; 2],
+) -> Option<bool> {
+    let fcinfo = pg_sys::palloc0(
+        mem::size_of::<pg_sys::FunctionCallInfoBaseData>()
+            + mem::size_of::<pg_sys::NullableDatum>() * args.len(),
+    )
+    .cast::<pg_sys::FunctionCallInfoBaseData>();
+    (*fcinfo).flinfo = flinfo;
+    (*fcinfo).fncollation = collation;
+    (*fcinfo).nargs = args.len() as i16;
+    let fcinfo_args = slice::from_raw_parts_mut(
+        ptr::addr_of_mut!((*fcinfo).args).cast::<pg_sys::NullableDatum>(),
+        args.len(),
+    );
+    for (arg, value) in fcinfo_args.iter_mut().zip(args) {
+        arg.value = value;
+        arg.isnull = false;
+    }
+
+    // The procedure may raise an error, which has to be turned into a panic
+    // to unwind the Rust frames.
+    let fn_addr = flinfo.fn_addr.unwrap();
+    let result = pg_sys::ffi::pg_guard_ffi_boundary(|| fn_addr(fcinfo));
+    let result = (!(*fcinfo).isnull).then(|| result.value() != 0);
+    pg_sys::pfree(fcinfo.cast());
+    result
+}
@@ -0,0 +1,102 @@
+use std::{ffi::CStr, time::Duration};
+
+use pgrx::{
+    bgworkers::{BackgroundWorker, BackgroundWorkerBuilder, BgWorkerStartTime, SignalWakeFlags},
+    pg_sys,
+    prelude::*,
+};
+
+use crate::{gin, gucs};
+
+// Registers a flush worker for each database of pg_bigmr.flush_databases,
+// which must be done while loading shared_preload_libraries.
+pub fn register() {
+    for database in gucs::flush_databases() {
+        BackgroundWorkerBuilder::new(&format!("pg_bigmr flush worker for {database}"))
+            .set_type("pg_bigmr flush worker")
+            .set_library("pg_bigmr")
+            .set_function("pg_bigm_flush_worker_main")
+            .set_extra(&database)
+            .enable_spi_access()
+            // The pending lists can't be flushed during recovery.
+            .set_start_time(BgWorkerStartTime::RecoveryFinished)
+            .set_restart_time(Some(Duration::from_secs(gucs::flush_naptime() as u64)))
+            .load();
+    }
+}
+
+// Flushes the pending lists larger than pg_bigmr.flush_threshold in the
+// database every pg_bigmr.flush_naptime, until SIGTERM.
+#[pg_guard]
+#[no_mangle]
+pub extern "C" fn pg_bigm_flush_worker_main(_arg: pg_sys::Datum) {
+    BackgroundWorker::attach_signal_handlers(SignalWakeFlags::SIGHUP | SignalWakeFlags::SIGTERM);
+    BackgroundWorker::connect_worker_to_spi(Some(BackgroundWorker::get_extra()), None);
+
+    // The configuration file is reloaded by the SIGHUP handler, so the
+    // settings are read again on every round.
+    while BackgroundWorker::wait_latch(Some(Duration::from_secs(gucs::flush_naptime() as u64))) {
+        let indexes = BackgroundWorker::transaction(|| {
+            large_pending_lists(gucs::flush_threshold()).unwrap_or_else(|e| pgrx::error!("{e}"))
+        });
+        for index_oid in indexes {
+            BackgroundWorker::transaction(|| unsafe { flush_index(index_oid) });
+        }
+    }
+}
+
+// Returns the indexes whose pending lists have more pages than the threshold,
+// or nothing if pg_bigmr is not installed in the database.
+fn large_pending_lists(threshold: i32) -> spi::Result<Vec<pg_sys::Oid>> {
+    Spi::connect(|client| {
+        let schema = client
+            .select(
+                "SELECT (SELECT extnamespace::regnamespace::text FROM pg_catalog.pg_extension \
+                         WHERE extname = 'pg_bigmr')",
+                None,
+                None,
+            )?
+            .first()
+            .get::<String>(1)?;
+        let Some(schema) = schema else {
+            return Ok(Vec::new());
+        };
+        client
+            .select(
+                &format!(
+                    "SELECT format('%I.%I', schemaname, indexname)::regclass::oid \
+                     FROM {schema}.pg_bigm_pending_stats WHERE pages > $1"
+                ),
+                None,
+                Some(vec![(PgBuiltInOids::INT4OID.oid(), threshold.into_datum())]),
+            )?
+            .filter_map(|row| row.get::<pg_sys::Oid>(1).transpose())
+            .collect()
+    })
+}
+
+// Flushes the whole pending list of the index unless it has been dropped or
+// is locked by others, e.g. being reindexed.
+unsafe fn flush_index(index_oid: pg_sys::Oid) {
+    let lockmode = pg_sys::RowExclusiveLock as pg_sys::LOCKMODE;
+    if !pg_sys::ConditionalLockRelationOid(index_oid, lockmode) {
+        return;
+    }
+    if !pg_sys::SearchSysCacheExists(
+        pg_sys::SysCacheIdentifier::RELOID as _,
+        index_oid.into(),
+        0.into(),
+        0.into(),
+        0.into(),
+    ) {
+        pg_sys::UnlockRelationOid(index_oid, lockmode);
+        return;
+    }
+
+    let (pages, tuples) = gin::flush_pending_list(index_oid);
+    pgrx::debug1!(
+        "flushed {pages} pages and {tuples} tuples of the pending list of index \"{}\"",
+        CStr::from_ptr(pg_sys::get_rel_name(index_oid)).to_string_lossy()
+    );
+    pg_sys::UnlockRelationOid(index_oid, lockmode);
+}
@@ -1,3 +1,5 @@
+-- run without shared_preload_libraries, where CREATE EXTENSION loads pg_bigmr
+-- after startup, see installcheck
 CREATE EXTENSION pg_bigmr;
 
 \pset null '(null)'
@@ -16,6 +18,31 @@ SET extra_float_digits TO 0;
 SHOW pg_bigmr.last_update;
 SET pg_bigmr.last_update = '2013.09.18';
 
+-- tests for migrate_from_pg_bigm.sql, showing the statements made from the
+-- indexes of pg_bigmr instead of pg_bigm
+SET allow_in_place_tablespaces = on;
+CREATE TABLESPACE regress_bigm_tblspc LOCATION '';
+CREATE TABLE test_bigm_migrate (col1 text, col2 int) PARTITION BY RANGE (col2);
+CREATE TABLE test_bigm_migrate_1 PARTITION OF test_bigm_migrate FOR VALUES FROM (0) TO (10);
+CREATE INDEX test_bigm_migrate_idx ON test_bigm_migrate USING gin (col1 gin_bigm_ops);
+CREATE INDEX test_bigm_migrate_part_idx ON test_bigm_migrate_1 USING gin (col1 gin_bigm_ops)
+  WITH (fastupdate = off) TABLESPACE regress_bigm_tblspc WHERE col2 > 1;
+CREATE UNIQUE INDEX test_bigm_migrate_unique_idx ON test_bigm_migrate_1 (col1, col2);
+COMMENT ON INDEX test_bigm_migrate_part_idx IS 'bigm''s index';
+SELECT c.relname FROM pg_inherits JOIN pg_class c ON c.oid = inhrelid
+  WHERE inhparent = 'test_bigm_migrate_idx'::regclass;
+\set ECHO none
+\set dry_run on
+\set from_extension pg_bigmr
+\i sql/migrate_from_pg_bigm.sql
+\unset dry_run
+\unset from_extension
+\set ON_ERROR_STOP off
+\set ECHO all
+SELECT count(*) FROM pg_class WHERE relname LIKE 'test_bigm_migrate%idx';
+DROP TABLE test_bigm_migrate;
+DROP TABLESPACE regress_bigm_tblspc;
+
 -- tests for likequery
 SELECT likequery(NULL);
 SELECT likequery('');
@@ -47,12 +74,88 @@ CREATE INDEX test_bigm_idx ON test_bigm
 -- this regression test whatever block size is used in PostgreSQL server.
 SELECT tuples FROM pg_gin_pending_stats('test_bigm_idx'::regclass);
 VACUUM;
-SELECT * FROM pg_gin_pending_stats('test_bigm_idx'::regclass);
+SELECT pages, tuples, head, tail, version
+  FROM pg_gin_pending_stats('test_bigm_idx'::regclass);
+SELECT total_pages > entry_pages + data_pages AS total_pages, entries > 0 AS entries
+  FROM pg_gin_pending_stats('test_bigm_idx'::regclass);
 SELECT * FROM pg_gin_pending_stats('test_bigm'::regclass);
 CREATE INDEX test_bigm_btree ON test_bigm USING btree (col2);
 SELECT * FROM pg_gin_pending_stats('test_bigm_btree'::regclass);
 DROP INDEX test_bigm_btree;
 
+-- tests for pg_bigm_flush_pending
+CREATE TABLE test_bigm_pending (col1 text, col2 text) WITH (autovacuum_enabled = off);
+CREATE INDEX test_bigm_pending_idx ON test_bigm_pending
+			 USING gin (col1 gin_bigm_ilike_ops, col2 gin_bigm_int_ilike_ops)
+			 WITH (fastupdate = on);
+SELECT * FROM pg_bigm_flush_pending('test_bigm_pending_idx'::regclass);
+INSERT INTO test_bigm_pending
+  SELECT CASE WHEN i % 100 = 0 THEN NULL WHEN i % 100 = 1 THEN '' ELSE 'Pending ' || i END,
+		 'List ' || (i % 7)
+  FROM generate_series(1, 2000) i;
+-- the numbers of the pages depend on the block size
+SELECT pages > 2 AS pages, tuples FROM pg_gin_pending_stats('test_bigm_pending_idx'::regclass);
+CREATE TEMP TABLE test_bigm_pending_stats AS
+  SELECT * FROM pg_gin_pending_stats('test_bigm_pending_idx'::regclass);
+-- the rows in the pending list are found as well
+SELECT count(*) FROM test_bigm_pending WHERE col1 LIKE '%ing 1_';
+SELECT count(*) FROM test_bigm_pending WHERE col1 LIKE '%ing 19__';
+-- the whole pending list is moved
+SELECT f.pages = p.pages AS pages, f.tuples
+  FROM pg_bigm_flush_pending('test_bigm_pending_idx'::regclass) f, test_bigm_pending_stats p;
+SELECT pages, tuples FROM pg_gin_pending_stats('test_bigm_pending_idx'::regclass);
+SELECT * FROM pg_bigm_flush_pending('test_bigm_pending_idx'::regclass);
+SELECT count(*) FROM test_bigm_pending WHERE col1 LIKE '%ing 1_';
+SELECT count(*) FROM test_bigm_pending WHERE col1 LIKE '%ing 19__';
+SELECT count(*) FROM test_bigm_pending WHERE col2 LIKE '%ist 3';
+-- every row must be in the main tree now
+SELECT s.attnum, count(*) FILTER (WHERE s.postings <> (
+		 SELECT count(*)
+		   FROM test_bigm_pending, LATERAL (SELECT CASE s.attnum WHEN 1 THEN col1 ELSE col2 END) AS c(v)
+		   WHERE s.bigram = ANY(show_bigm(CASE WHEN s.casefold THEN lower(c.v) ELSE c.v END))
+			 AND NOT (s.casefold AND s.bigram = ANY(show_bigm(c.v)))))
+		 AS mismatches
+  FROM pg_bigm_index_stats('test_bigm_pending_idx'::regclass) s GROUP BY s.attnum ORDER BY s.attnum;
+SELECT * FROM pg_bigm_flush_pending('test_bigm_pending'::regclass);
+SELECT * FROM pg_bigm_flush_pending(NULL);
+INSERT INTO test_bigm_pending VALUES ('Pending NULL', 'List NULL');
+SELECT * FROM pg_bigm_flush_pending('test_bigm_pending_idx'::regclass);
+CREATE ROLE regress_bigm_user;
+GRANT SELECT ON test_bigm_pending TO regress_bigm_user;
+SET ROLE regress_bigm_user;
+SELECT * FROM pg_bigm_flush_pending('test_bigm_pending_idx'::regclass);
+RESET ROLE;
+REVOKE SELECT ON test_bigm_pending FROM regress_bigm_user;
+DROP ROLE regress_bigm_user;
+DROP TABLE test_bigm_pending, test_bigm_pending_stats;
+
+-- tests for pg_bigm_pending_stats
+CREATE TABLE test_bigm_pending (col1 text) WITH (autovacuum_enabled = off);
+CREATE INDEX test_bigm_pending_idx ON test_bigm_pending
+			 USING gin (col1 gin_bigm_ops) WITH (gin_pending_list_limit = 128);
+CREATE INDEX test_bigm_pending_int_idx ON test_bigm_pending
+			 USING gin (col1 gin_bigm_int_ops) WITH (fastupdate = off);
+CREATE INDEX test_bigm_pending_array_idx ON test_bigm_pending USING gin ((ARRAY[col1]));
+CREATE TEMP TABLE test_bigm_pending_temp (col1 text);
+CREATE INDEX test_bigm_pending_temp_idx ON test_bigm_pending_temp USING gin (col1 gin_bigm_ops);
+INSERT INTO test_bigm_pending SELECT 'pending ' || i FROM generate_series(1, 10) i;
+INSERT INTO test_bigm_pending_temp SELECT 'pending ' || i FROM generate_series(1, 5) i;
+BEGIN;
+SET LOCAL gin_pending_list_limit = '1MB';
+SELECT schemaname = 'public' AS public, tablename, indexname, pages, tuples,
+	   fastupdate, gin_pending_list_limit
+  FROM pg_bigm_pending_stats WHERE tablename LIKE 'test_bigm_pending%' ORDER BY indexname;
+COMMIT;
+DROP TABLE test_bigm_pending, test_bigm_pending_temp;
+
+-- tests for the settings of the flush worker
+SHOW pg_bigmr.flush_naptime;
+SHOW pg_bigmr.flush_threshold;
+SET pg_bigmr.flush_naptime = '10s';
+-- pg_bigmr.flush_databases and pg_bigmr.pg_bigm_compatibility are only
+-- defined by shared_preload_libraries, see pg_bigmr_compat.sql
+SELECT name FROM pg_settings WHERE name LIKE 'pg\_bigmr.%' AND context = 'postmaster';
+
 -- tests for full-text search
 EXPLAIN (COSTS off) SELECT * FROM test_bigm WHERE col1 LIKE likequery('a');
 EXPLAIN (COSTS off) SELECT * FROM test_bigm WHERE col1 LIKE likequery('am');
@@ -111,6 +214,49 @@ SELECT count(*) FROM test_bigm WHERE col1 LIKE likequery('she tore');
 SET pg_bigmr.gin_key_limit = 1;
 SELECT count(*) FROM test_bigm WHERE col1 LIKE likequery('she tore');
 
+-- tests for pg_bigmr.gin_key_selection
+SELECT pg_bigm_collect_key_stats('test_bigm_idx');
+SELECT key, attnum, frequency FROM pg_bigm_key_stats
+  WHERE indexrelid = 'test_bigm_idx'::regclass
+    AND key IN (' s', 'sh', 'he', 'e ', ' t', 'to', 'or', 're') ORDER BY key, attnum;
+SET pg_bigmr.gin_key_selection = rarest;
+SELECT count(*) FROM test_bigm WHERE col1 LIKE likequery('she tore');
+SELECT count(*) FROM test_bigm WHERE col1 ~ 'she tore';
+SET pg_bigmr.gin_key_limit = 3;
+SELECT count(*) FROM test_bigm WHERE col1 LIKE likequery('she tore');
+-- the table of the extension is used whatever search_path is
+CREATE TEMP TABLE pg_bigm_key_stats (key text, frequency int8);
+INSERT INTO pg_bigm_key_stats VALUES (' s', 1), ('sh', 1), ('he', 1);
+SET search_path = pg_catalog;
+SELECT count(*) FROM public.test_bigm WHERE col1 LIKE public.likequery('she tore');
+RESET search_path;
+DROP TABLE pg_temp.pg_bigm_key_stats;
+SET pg_bigmr.gin_key_limit = 1;
+RESET pg_bigmr.gin_key_selection;
+-- the frequencies are kept for each index until it's dropped
+CREATE INDEX test_bigm_key_stats_idx ON test_bigm USING gin (col1 gin_bigm_ops) WITH (fastupdate = off);
+SELECT pg_bigm_collect_key_stats('test_bigm_key_stats_idx');
+SELECT indexrelid::regclass, attnum, count(*), sum(frequency) FROM pg_bigm_key_stats
+  GROUP BY 1, 2 ORDER BY 1, 2;
+DROP INDEX test_bigm_key_stats_idx;
+-- the frequencies of the index searched are used
+CREATE TABLE test_bigm_key_dist (col1 text, col2 text);
+INSERT INTO test_bigm_key_dist VALUES
+  ('abc', 'abc'), ('ab', 'ab'), ('ab', 'bc'), ('ab', 'bc'), ('ab', 'bc'),
+  ('ab', NULL), ('ab', NULL), ('bc', NULL);
+CREATE INDEX test_bigm_key_dist_idx1 ON test_bigm_key_dist USING gin (col1 gin_bigm_ops);
+CREATE INDEX test_bigm_key_dist_idx2 ON test_bigm_key_dist USING gin (col2 gin_bigm_ops);
+SELECT pg_bigm_collect_key_stats('test_bigm_key_dist_idx1');
+SELECT pg_bigm_collect_key_stats('test_bigm_key_dist_idx2');
+SET pg_bigmr.gin_key_selection = rarest;
+SELECT count(*) FROM test_bigm_key_dist WHERE col1 LIKE '%abc%';
+SELECT count(*) FROM test_bigm_key_dist WHERE col2 LIKE '%abc%';
+RESET pg_bigmr.gin_key_selection;
+DROP TABLE test_bigm_key_dist;
+SELECT pg_bigm_collect_key_stats('test_bigm_idx');
+SELECT indexrelid::regclass, attnum, count(*), sum(frequency) FROM pg_bigm_key_stats
+  GROUP BY 1, 2 ORDER BY 1, 2;
+
 SET pg_bigmr.enable_recheck = on;
 SET pg_bigmr.gin_key_limit = 0;
 
@@ -133,6 +279,140 @@ SELECT * FROM test_bigm WHERE col2 LIKE likequery('meta');
 EXPLAIN (COSTS off) SELECT * FROM test_bigm WHERE col1 LIKE likequery('bigm');
 SELECT * FROM test_bigm WHERE col1 LIKE likequery('bigm');
 
+-- tests for case-insensitive search, whose keys are stored by
+-- gin_bigm_ilike_ops
+CREATE TABLE test_bigm_ilike AS SELECT col1 FROM test_bigm;
+CREATE INDEX test_bigm_ilike_idx ON test_bigm_ilike USING gin (col1 gin_bigm_ilike_ops);
+EXPLAIN (COSTS off) SELECT col1 FROM test_bigm_ilike WHERE col1 ILIKE likequery('gin');
+SELECT col1 FROM test_bigm_ilike WHERE col1 ILIKE likequery('gin');
+SELECT col1 FROM test_bigm_ilike WHERE col1 ILIKE likequery('TOOL');
+SELECT col1 FROM test_bigm_ilike WHERE col1 ILIKE likequery('PoStGrEsQl');
+SELECT col1 FROM test_bigm_ilike WHERE col1 ILIKE '%wal_file%';
+SET pg_bigmr.enable_recheck = off;
+SELECT col1 FROM test_bigm_ilike WHERE col1 ILIKE likequery('Trial');
+SET pg_bigmr.enable_recheck = on;
+SET pg_bigmr.gin_key_limit = 1;
+SELECT count(*) FROM test_bigm_ilike WHERE col1 ILIKE likequery('SHE TORE');
+SET pg_bigmr.gin_key_limit = 0;
+SELECT count(*) FROM test_bigm_ilike WHERE col1 ILIKE likequery('SHE TORE');
+SELECT pg_bigm_collect_key_stats('test_bigm_ilike_idx');
+SET pg_bigmr.gin_key_selection = rarest;
+SET pg_bigmr.gin_key_limit = 1;
+SET pg_bigmr.enable_recheck = off;
+SELECT count(*) FROM test_bigm_ilike WHERE col1 ILIKE likequery('SHE TORE');
+SET pg_bigmr.enable_recheck = on;
+SET pg_bigmr.gin_key_limit = 0;
+RESET pg_bigmr.gin_key_selection;
+
+-- tests for regular expression search
+EXPLAIN (COSTS off) SELECT col1 FROM test_bigm WHERE col1 ~ 'pg_bigm';
+EXPLAIN (COSTS off) SELECT col1 FROM test_bigm_ilike WHERE col1 ~* 'pg_bigm';
+SELECT col1 FROM test_bigm WHERE col1 ~ 'full text (search|index)';
+SELECT col1 FROM test_bigm WHERE col1 ~ '^Sets the (maximum|minimum) number of s';
+SELECT col1 FROM test_bigm WHERE col1 ~ '(?:WAL|xlog) file';
+SELECT col1 FROM test_bigm WHERE col1 ~ 'se+ssion';
+SELECT col1 FROM test_bigm WHERE col1 ~ '[0-9]-gram';
+SELECT col1 FROM test_bigm WHERE col1 ~ 't(he|ha)t';
+SELECT col1 FROM test_bigm WHERE col1 ~ '\\\\dx';
+SELECT col1 FROM test_bigm_ilike WHERE col1 ~* 'POSTGRESQL';
+SELECT col1 FROM test_bigm_ilike WHERE col1 ~* 'gin index';
+SELECT count(*) FROM test_bigm WHERE col1 ~ '(?=a)b';
+SELECT count(*) FROM test_bigm WHERE col1 ~ 'n.{1,3}e';
+SET pg_bigmr.enable_recheck = off;
+SELECT col1 FROM test_bigm WHERE col1 ~ 'trial|tool';
+SET pg_bigmr.enable_recheck = on;
+SET pg_bigmr.gin_key_limit = 1;
+SELECT col1 FROM test_bigm WHERE col1 ~ 'Tool|GIN index';
+SET pg_bigmr.gin_key_limit = 0;
+
+-- tests for bigm_query
+SELECT 'postgres & (index | gin) & !mysql'::bigm_query;
+SELECT '(a | b) | c & (d & e) & !(f | g) & !!h'::bigm_query;
+SELECT '"full text" & "say ""hi"""'::bigm_query;
+SELECT 'postgres &'::bigm_query;
+SELECT '(postgres'::bigm_query;
+SELECT ''::bigm_query;
+SELECT 'pg_bigm is fast' @@ 'bigm & !slow'::bigm_query, 'pg_bigm is slow' @@ 'bigm & !slow'::bigm_query;
+SELECT 'full text search' @@ '"text search" | xyz'::bigm_query;
+EXPLAIN (COSTS off) SELECT col1 FROM test_bigm WHERE col1 @@ 'performance & (planner | parser)'::bigm_query;
+SELECT col1 FROM test_bigm WHERE col1 @@ 'performance & (planner | parser)'::bigm_query ORDER BY col1;
+SELECT col1 FROM test_bigm WHERE col1 @@ 'performance & !Writes'::bigm_query ORDER BY col1;
+SELECT count(*) FROM test_bigm WHERE col1 @@ '!performance'::bigm_query;
+SET pg_bigmr.enable_recheck = off;
+SELECT col1 FROM test_bigm WHERE col1 @@ 'trial | "full text"'::bigm_query ORDER BY col1;
+SET pg_bigmr.enable_recheck = on;
+SELECT col1 FROM test_bigm WHERE col1 @@ 'trial | "full text"'::bigm_query ORDER BY col1;
+
+-- tests for LIKE ANY search
+SELECT 'pg_bigm' ~~| ARRAY['%bigm%', '%trgm%'], 'pg_bigm' ~~| ARRAY['%trgm%'], 'pg_bigm' ~~| ARRAY[]::text[];
+SELECT 'pg_bigm' ~~| ARRAY['%trgm%', NULL], 'pg_bigm' ~~| ARRAY[NULL, 'pg%'];
+EXPLAIN (COSTS off) SELECT col1 FROM test_bigm WHERE col1 ~~| ARRAY['%trial%', '%full text%'];
+SELECT col1 FROM test_bigm WHERE col1 ~~| ARRAY['%trial%', '%full text%'] ORDER BY col1;
+SELECT col1 FROM test_bigm WHERE col1 ~~| ARRAY['%planner%', 'Sets the % cost %', NULL] ORDER BY col1;
+SELECT count(*) FROM test_bigm WHERE col1 ~~| ARRAY['%a%', '%Y%'];
+SELECT count(*) FROM test_bigm WHERE col1 LIKE ANY (ARRAY['%a%', '%Y%']);
+SET pg_bigmr.enable_recheck = off;
+SELECT col1 FROM test_bigm WHERE col1 ~~| ARRAY['%trial%', '%Tool%'] ORDER BY col1;
+SET pg_bigmr.enable_recheck = on;
+
+-- tests for selectivity estimation
+CREATE FUNCTION estimated_rows(query text) RETURNS int8 AS $$
+DECLARE
+	plan json;
+BEGIN
+	EXECUTE 'EXPLAIN (FORMAT json) ' || query INTO plan;
+	RETURN (plan->0->'Plan'->>'Plan Rows')::int8;
+END;
+$$ LANGUAGE plpgsql;
+CREATE TABLE test_bigm_sel AS SELECT col1 FROM test_bigm;
+SELECT estimated_rows($$SELECT * FROM test_bigm_sel WHERE col1 =% 'tool'$$);
+ANALYZE test_bigm_sel;
+SELECT estimated_rows($$SELECT * FROM test_bigm_sel WHERE col1 @@ 'the'::bigm_query$$),
+       count(*) FROM test_bigm_sel WHERE col1 @@ 'the'::bigm_query;
+SELECT estimated_rows($$SELECT * FROM test_bigm_sel WHERE col1 @@ 'performance & !Writes'::bigm_query$$),
+       count(*) FROM test_bigm_sel WHERE col1 @@ 'performance & !Writes'::bigm_query;
+SELECT estimated_rows($$SELECT * FROM test_bigm_sel WHERE col1 @@ 'xyzzy'::bigm_query$$),
+       count(*) FROM test_bigm_sel WHERE col1 @@ 'xyzzy'::bigm_query;
+SELECT estimated_rows($$SELECT * FROM test_bigm_sel WHERE col1 ~~| ARRAY['%a%', '%trial%']$$),
+       count(*) FROM test_bigm_sel WHERE col1 ~~| ARRAY['%a%', '%trial%'];
+SELECT estimated_rows($$SELECT * FROM test_bigm_sel WHERE col1 =% 'tool'$$),
+       count(*) FROM test_bigm_sel WHERE col1 =% 'tool';
+SELECT estimated_rows($$SELECT * FROM test_bigm_sel WHERE 'tool' =% col1$$),
+       count(*) FROM test_bigm_sel WHERE 'tool' =% col1;
+SELECT estimated_rows($$SELECT * FROM test_bigm_sel WHERE col1 =% ROW('tool', 0.5)::bigm_similar_query$$),
+       count(*) FROM test_bigm_sel WHERE col1 =% ROW('tool', 0.5)::bigm_similar_query;
+SELECT estimated_rows($$SELECT * FROM test_bigm_sel WHERE col1 =% ROW(NULL, 0.5)::bigm_similar_query$$);
+SELECT estimated_rows($$SELECT * FROM test_bigm_sel WHERE col1 %> 'index'$$),
+       count(*) FROM test_bigm_sel WHERE col1 %> 'index';
+-- the sampled values aren't given to the operators for the users who can't see
+-- them, e.g. through row-level security
+ALTER TABLE test_bigm_sel ENABLE ROW LEVEL SECURITY;
+CREATE POLICY test_bigm_sel_policy ON test_bigm_sel USING (true);
+CREATE ROLE regress_bigm_user;
+GRANT SELECT ON test_bigm_sel TO regress_bigm_user;
+SET ROLE regress_bigm_user;
+SELECT estimated_rows($$SELECT * FROM test_bigm_sel WHERE col1 =% 'tool'$$),
+       estimated_rows($$SELECT * FROM test_bigm_sel WHERE col1 @@ 'the'::bigm_query$$);
+RESET ROLE;
+REVOKE SELECT ON test_bigm_sel FROM regress_bigm_user;
+DROP ROLE regress_bigm_user;
+DROP POLICY test_bigm_sel_policy ON test_bigm_sel;
+ALTER TABLE test_bigm_sel DISABLE ROW LEVEL SECURITY;
+-- the most common bigrams of the index on to_bigm() of the column are used
+CREATE INDEX test_bigm_sel_idx ON test_bigm_sel USING gin (to_bigm(col1));
+ANALYZE test_bigm_sel;
+SELECT estimated_rows($$SELECT * FROM test_bigm_sel WHERE col1 =% 'tool'$$),
+       count(*) FROM test_bigm_sel WHERE col1 =% 'tool';
+SELECT estimated_rows($$SELECT * FROM test_bigm_sel WHERE col1 =% 'pg_bigm is a module'$$),
+       count(*) FROM test_bigm_sel WHERE col1 =% 'pg_bigm is a module';
+SELECT estimated_rows($$SELECT * FROM test_bigm_sel WHERE col1 @@ 'the'::bigm_query$$),
+       count(*) FROM test_bigm_sel WHERE col1 @@ 'the'::bigm_query;
+SELECT estimated_rows($$SELECT * FROM test_bigm_sel WHERE col1 @@ 'xyzzy'::bigm_query$$),
+       count(*) FROM test_bigm_sel WHERE col1 @@ 'xyzzy'::bigm_query;
+SELECT estimated_rows($$SELECT * FROM test_bigm_sel WHERE col1 ~~| ARRAY['%a%', '%trial%']$$),
+       count(*) FROM test_bigm_sel WHERE col1 ~~| ARRAY['%a%', '%trial%'];
+DROP TABLE test_bigm_sel;
+
 -- tests for bigm_similarity
 SELECT bigm_similarity('wow', NULL);
 SELECT bigm_similarity('wow', '');
@@ -173,6 +453,329 @@ SELECT count(*), max(bigm_similarity(col1, 'Tool')) FROM test_bigm WHERE NOT col
 SELECT count(*), min(bigm_similarity(col1, 'performance')) FROM test_bigm WHERE col1 =% 'performance';
 SELECT count(*), max(bigm_similarity(col1, 'performance')) FROM test_bigm WHERE NOT col1 =% 'performance';
 
+-- tests for pg_bigmr.similarity_formula
+SET pg_bigmr.similarity_formula = 'union';
+SELECT bigm_similarity('wow', 'wowa');
+SELECT bigm_similarity('word', 'two words');
+SELECT count(*), min(bigm_similarity(col1, 'Tool')) FROM test_bigm WHERE col1 =% 'Tool';
+SELECT count(*), max(bigm_similarity(col1, 'Tool')) FROM test_bigm WHERE NOT col1 =% 'Tool';
+SET pg_bigmr.similarity_formula = dice;
+SELECT bigm_similarity('wow', 'wowa');
+SELECT bigm_similarity('word', 'two words');
+SELECT count(*), min(bigm_similarity(col1, 'Tool')) FROM test_bigm WHERE col1 =% 'Tool';
+SELECT count(*), max(bigm_similarity(col1, 'Tool')) FROM test_bigm WHERE NOT col1 =% 'Tool';
+SET pg_bigmr.similarity_formula = overlap;
+SELECT bigm_similarity('wow', 'wowa');
+SELECT bigm_similarity('word', 'two words');
+SELECT count(*), min(bigm_similarity(col1, 'Tool')) FROM test_bigm WHERE col1 =% 'Tool';
+SELECT count(*), max(bigm_similarity(col1, 'Tool')) FROM test_bigm WHERE NOT col1 =% 'Tool';
+SET pg_bigmr.similarity_formula = max;
+SHOW pg_bigmr.similarity_formula;
+SELECT bigm_similarity('word', 'two words');
+SET pg_bigmr.similarity_formula = jaccard;
+RESET pg_bigmr.similarity_formula;
+-- the functions depending on the formula must not be folded into constants
+PREPARE bigm_similarity_plan AS
+  SELECT bigm_similarity('abc', 'abcdefgh'), 'abc' <-> 'abcdefgh',
+         bigm_similarity(to_bigm('abc'), to_bigm('abcdefgh')),
+         to_bigm('abc') <-> to_bigm('abcdefgh');
+EXECUTE bigm_similarity_plan;
+SET pg_bigmr.similarity_formula = overlap;
+EXECUTE bigm_similarity_plan;
+RESET pg_bigmr.similarity_formula;
+DEALLOCATE bigm_similarity_plan;
+SELECT p.oid::regprocedure, p.provolatile FROM pg_proc p
+  WHERE p.proname IN ('bigm_similarity', 'bigm_distance', 'bigm_vector_distance',
+                      'bigm_word_similarity', 'bigm_strict_word_similarity', 'gist_bigm_distance')
+  ORDER BY p.oid::regprocedure::text;
+
+-- tests for similarity search with its own threshold
+SELECT 'wow' =% ROW('wowa', 0.5)::bigm_similar_query;
+SELECT 'wow' =% ROW('wowa', 0.7)::bigm_similar_query;
+SELECT 'wow' =% ROW(NULL, 0.5)::bigm_similar_query;
+SELECT bigm_similar('wow', 'wowa', 0.5), bigm_similar('wow', 'wowa', 0.7);
+EXPLAIN (COSTS off) SELECT * FROM test_bigm WHERE bigm_similar(col1, 'bigm', 0.5);
+
+SELECT count(*), min(bigm_similarity(col1, 'Tool')) FROM test_bigm WHERE bigm_similar(col1, 'Tool', 0.06);
+SELECT count(*), max(bigm_similarity(col1, 'Tool')) FROM test_bigm WHERE NOT bigm_similar(col1, 'Tool', 0.06);
+SELECT count(*), min(bigm_similarity(col1, 'performance')) FROM test_bigm WHERE bigm_similar(col1, 'performance', 0.2);
+SELECT count(*), max(bigm_similarity(col1, 'performance')) FROM test_bigm WHERE NOT bigm_similar(col1, 'performance', 0.2);
+SELECT count(*) FROM test_bigm WHERE bigm_similar(col1, 'Tool', 0.06) AND bigm_similar(col1, 'performance', 0.05);
+-- NULL threshold means pg_bigmr.similarity_limit
+SELECT count(*) FROM test_bigm WHERE bigm_similar(col1, 'Tool', NULL);
+
+-- tests for word similarity
+SELECT bigm_word_similarity('wow', NULL);
+SELECT bigm_word_similarity('wow', '');
+SELECT bigm_word_similarity('wow', 'WOW');
+SELECT bigm_word_similarity('word', 'two words');
+SELECT bigm_word_similarity('two words', 'word');
+SELECT bigm_strict_word_similarity('word', 'two words');
+SELECT bigm_strict_word_similarity('word', 'two word');
+-- long texts take a single pass
+SELECT bigm_word_similarity('abq', repeat('ab ', 100000));
+SELECT bigm_strict_word_similarity('abq', repeat('ab ', 100000));
+SELECT 'word' <% 'two words', 'two words' %> 'word';
+SELECT 'word' <<% 'two words', 'two words' %>> 'word';
+
+EXPLAIN (COSTS off) SELECT * FROM test_bigm WHERE col1 %> 'bigm';
+EXPLAIN (COSTS off) SELECT * FROM test_bigm WHERE col1 %>> 'bigm';
+
+SELECT count(*), min(bigm_word_similarity('Tool', col1)) FROM test_bigm WHERE col1 %> 'Tool';
+SELECT count(*), max(bigm_word_similarity('Tool', col1)) FROM test_bigm WHERE NOT col1 %> 'Tool';
+SELECT count(*), min(bigm_word_similarity('performance', col1)) FROM test_bigm WHERE col1 %> 'performance';
+SELECT count(*), max(bigm_word_similarity('performance', col1)) FROM test_bigm WHERE NOT col1 %> 'performance';
+SELECT count(*), min(bigm_strict_word_similarity('text', col1)) FROM test_bigm WHERE col1 %>> 'text';
+SELECT count(*), max(bigm_strict_word_similarity('text', col1)) FROM test_bigm WHERE NOT col1 %>> 'text';
+SET pg_bigmr.word_similarity_limit = 0.9;
+SELECT col1 FROM test_bigm WHERE col1 %> 'trial';
+SET pg_bigmr.strict_word_similarity_limit = 0.9;
+SELECT col1 FROM test_bigm WHERE col1 %>> 'trial';
+RESET pg_bigmr.word_similarity_limit;
+RESET pg_bigmr.strict_word_similarity_limit;
+
+-- tests for opclass options of gin_bigm_ops
+CREATE TABLE test_bigm_options AS SELECT * FROM test_bigm;
+CREATE INDEX test_bigm_options_idx ON test_bigm_options USING gin (col1 gin_bigm_ops(gin_key_limit = -2));
+CREATE INDEX test_bigm_options_idx ON test_bigm_options USING gin (col1 gin_bigm_ops(gin_key_limit = 1));
+SET pg_bigmr.enable_recheck = off;
+SELECT count(*) FROM test_bigm_options WHERE col1 LIKE likequery('she tore');
+SET pg_bigmr.gin_key_limit = 6;
+SELECT count(*) FROM test_bigm_options WHERE col1 LIKE likequery('she tore');
+SET pg_bigmr.gin_key_limit = 0;
+SET pg_bigmr.enable_recheck = on;
+DROP INDEX test_bigm_options_idx;
+
+-- gin_bigm_ops has no case-folded keys, so ILIKE and ~* don't use it
+CREATE INDEX test_bigm_options_idx ON test_bigm_options USING gin (col1 gin_bigm_ops);
+SELECT count(*) FILTER (WHERE casefold) FROM pg_bigm_index_stats('test_bigm_options_idx'::regclass);
+EXPLAIN (COSTS off) SELECT col1 FROM test_bigm_options WHERE col1 ILIKE likequery('Trial');
+EXPLAIN (COSTS off) SELECT col1 FROM test_bigm_options WHERE col1 ~* 'wal (file|sender)';
+SET pg_bigmr.enable_recheck = off;
+SELECT col1 FROM test_bigm_options WHERE col1 ILIKE likequery('Trial') ORDER BY col1;
+SELECT col1 FROM test_bigm_options WHERE col1 ~* 'wal (file|sender)' ORDER BY col1;
+SET pg_bigmr.enable_recheck = on;
+DROP INDEX test_bigm_options_idx;
+
+-- gin_bigm_ilike_ops finds the rows by the case-folded keys
+CREATE INDEX test_bigm_options_idx ON test_bigm_options USING gin (col1 gin_bigm_ilike_ops);
+EXPLAIN (COSTS off) SELECT col1 FROM test_bigm_options WHERE col1 ILIKE likequery('PERFORMANCE');
+SET pg_bigmr.enable_recheck = off;
+SELECT col1 FROM test_bigm_options WHERE col1 LIKE likequery('PERFORMANCE') ORDER BY col1;
+SELECT col1 FROM test_bigm_options WHERE col1 ILIKE likequery('PERFORMANCE') ORDER BY col1;
+SET pg_bigmr.enable_recheck = on;
+-- the case-folded keys don't depend on the characters around them
+INSERT INTO test_bigm_options VALUES ('ΟΣΑ');
+SELECT col1 FROM test_bigm_options WHERE col1 ILIKE '%ΟΣ%';
+SELECT col1 FROM test_bigm_options WHERE col1 ~* 'ΟΣ';
+DELETE FROM test_bigm_options WHERE col1 = 'ΟΣΑ';
+-- each character is case-folded into one like lower() does
+INSERT INTO test_bigm_options VALUES ('İSTANBUL');
+SET pg_bigmr.enable_recheck = off;
+SELECT col1 FROM test_bigm_options WHERE col1 ILIKE '%istanbul%';
+SELECT col1 FROM test_bigm_options WHERE col1 ~* 'İstanbul';
+SET pg_bigmr.enable_recheck = on;
+DELETE FROM test_bigm_options WHERE col1 = 'İSTANBUL';
+DROP INDEX test_bigm_options_idx;
+
+-- tests for n-gram sizes
+SELECT show_ngrams('abc', 1);
+SELECT show_ngrams('abc', 2) = show_bigm('abc');
+SELECT show_ngrams('ab cd', 3);
+SELECT show_ngrams('abc', 0);
+SELECT show_ngrams('abc', 4);
+
+CREATE INDEX test_bigm_options_idx ON test_bigm_options USING gin (col1 gin_bigm_ops(gram_size = 4));
+CREATE INDEX test_bigm_options_idx ON test_bigm_options USING gin (col1 gin_bigm_ops(gram_size = 3));
+SELECT col1 FROM test_bigm_options WHERE col1 LIKE likequery('performance') ORDER BY col1;
+SELECT col1 FROM test_bigm_options WHERE col1 LIKE likequery('pi') ORDER BY col1;
+SELECT col1 FROM test_bigm_options WHERE col1 ~ 'stat[a-z]*s' ORDER BY col1;
+SELECT count(*), min(bigm_similarity(col1, 'performance')) FROM test_bigm_options WHERE col1 =% 'performance';
+SET pg_bigmr.enable_recheck = off;
+SELECT col1 FROM test_bigm_options WHERE col1 LIKE likequery('performance') ORDER BY col1;
+SET pg_bigmr.enable_recheck = on;
+DROP INDEX test_bigm_options_idx;
+
+CREATE INDEX test_bigm_options_idx ON test_bigm_options USING gin (col1 gin_bigm_ops(gram_size = 1));
+SELECT col1 FROM test_bigm_options WHERE col1 LIKE likequery('GIN') ORDER BY col1;
+DROP INDEX test_bigm_options_idx;
+
+CREATE INDEX test_bigm_options_idx ON test_bigm_options USING gin (col1 gin_bigm_ops(mixed_script = true));
+SELECT col1 FROM test_bigm_options WHERE col1 LIKE likequery('performance') ORDER BY col1;
+SELECT col1 FROM test_bigm_options WHERE col1 LIKE likequery('pi') ORDER BY col1;
+DROP TABLE test_bigm_options;
+
+-- tests for gin_bigm_int_ops
+CREATE TABLE test_bigm_int AS SELECT * FROM test_bigm;
+CREATE INDEX test_bigm_int_idx ON test_bigm_int USING gin (col1 gin_bigm_int_ilike_ops);
+EXPLAIN (COSTS off) SELECT * FROM test_bigm_int WHERE col1 LIKE likequery('bigm');
+SELECT col1 FROM test_bigm_int WHERE col1 LIKE likequery('performance') ORDER BY col1;
+SELECT col1 FROM test_bigm_int WHERE col1 ILIKE likequery('TOOL') ORDER BY col1;
+SELECT col1 FROM test_bigm_int WHERE col1 ~* 'wal (file|sender)' ORDER BY col1;
+SELECT count(*), min(bigm_similarity(col1, 'Tool')) FROM test_bigm_int WHERE col1 =% 'Tool';
+SELECT count(*) FROM test_bigm_int WHERE col1 %> 'statistic';
+-- the results without recheck must be the same as the ones of gin_bigm_ops
+SET pg_bigmr.enable_recheck = off;
+SELECT q, (SELECT count(*) FROM test_bigm_int WHERE col1 LIKE q) AS int_ops,
+       (SELECT count(*) FROM test_bigm WHERE col1 LIKE q) AS text_ops
+  FROM unnest(ARRAY['%a%', '%pi%', '%trial%', '%she tore%', '%Y%', 'Y']) AS q;
+SELECT q, (SELECT count(*) FROM test_bigm_int WHERE col1 ILIKE q) AS int_ops,
+       (SELECT count(*) FROM test_bigm_ilike WHERE col1 ILIKE q) AS text_ops
+  FROM unnest(ARRAY['%Trial%', '%GIN%']) AS q;
+SET pg_bigmr.enable_recheck = on;
+DROP TABLE test_bigm_int;
+
+-- tests for pg_bigm_index_stats
+SELECT * FROM pg_bigm_index_stats('test_bigm_idx'::regclass, 5);
+-- the postings must be the numbers of the rows including the bigrams, where
+-- case-folded keys are the bigrams appearing only after lower-casing
+SELECT s.casefold, count(*), count(*) FILTER (WHERE s.postings <> (
+         SELECT count(*)
+           FROM test_bigm_ilike
+           WHERE s.bigram = ANY(show_bigm(CASE WHEN s.casefold THEN lower(col1) ELSE col1 END))
+             AND NOT (s.casefold AND s.bigram = ANY(show_bigm(col1)))))
+         AS mismatches
+  FROM pg_bigm_index_stats('test_bigm_ilike_idx'::regclass) s GROUP BY s.casefold ORDER BY s.casefold;
+DROP TABLE test_bigm_ilike;
+CREATE TABLE test_bigm_stats AS SELECT 'PostgreSQL ' || i AS col1 FROM generate_series(1, 20000) i;
+CREATE INDEX test_bigm_stats_idx ON test_bigm_stats USING gin (col1 gin_bigm_int_ops);
+SELECT * FROM pg_bigm_index_stats('test_bigm_stats_idx'::regclass, 4);
+SELECT * FROM pg_bigm_index_stats('test_bigm_stats_idx'::regclass) WHERE bigram IN ('SQ', '99');
+SELECT * FROM pg_bigm_index_stats('test_bigm_stats_idx'::regclass, 0);
+SELECT * FROM pg_bigm_index_stats('test_bigm_stats_idx'::regclass, -1);
+SELECT * FROM pg_bigm_index_stats('test_bigm_stats'::regclass);
+CREATE INDEX test_bigm_stats_array_idx ON test_bigm_stats USING gin ((ARRAY[col1]));
+SELECT * FROM pg_bigm_index_stats('test_bigm_stats_array_idx'::regclass);
+-- only the users who can read every row of the table can see the keys
+CREATE ROLE regress_bigm_user;
+SET ROLE regress_bigm_user;
+SELECT count(*) > 0 AS shown FROM pg_bigm_index_stats('test_bigm_stats_idx'::regclass);
+RESET ROLE;
+GRANT EXECUTE ON FUNCTION pg_bigm_index_stats(oid, int4) TO regress_bigm_user;
+SET ROLE regress_bigm_user;
+SELECT count(*) > 0 AS shown FROM pg_bigm_index_stats('test_bigm_stats_idx'::regclass);
+RESET ROLE;
+GRANT SELECT ON test_bigm_stats TO regress_bigm_user;
+ALTER TABLE test_bigm_stats ENABLE ROW LEVEL SECURITY;
+SET ROLE regress_bigm_user;
+SELECT count(*) > 0 AS shown FROM pg_bigm_index_stats('test_bigm_stats_idx'::regclass);
+RESET ROLE;
+ALTER TABLE test_bigm_stats DISABLE ROW LEVEL SECURITY;
+SET ROLE regress_bigm_user;
+SELECT count(*) > 0 AS shown FROM pg_bigm_index_stats('test_bigm_stats_idx'::regclass);
+RESET ROLE;
+REVOKE SELECT ON test_bigm_stats FROM regress_bigm_user;
+REVOKE EXECUTE ON FUNCTION pg_bigm_index_stats(oid, int4) FROM regress_bigm_user;
+DROP ROLE regress_bigm_user;
+DROP TABLE test_bigm_stats;
+
+-- tests for bigm type
+SELECT to_bigm('pg_bigm');
+SELECT to_bigm('pg_bigm')::text::bigm::text = to_bigm('pg_bigm')::text AS roundtrip;
+SELECT $$ 'pg' ' p'  'pg' 'g ' $$::bigm;
+SELECT $$'t''' '''s'$$::bigm;
+SELECT ''::bigm, to_bigm('');
+SELECT 'pg'::bigm;
+SELECT '''abc'''::bigm;
+SELECT '''pg'''::bigm;
+SELECT bigm_send(to_bigm('ab'));
+SELECT bigm_similarity(to_bigm('wow'), to_bigm('WOWa ')) = bigm_similarity('wow', 'WOWa ') AS same;
+SELECT bigm_similarity(to_bigm('full text search'), to_bigm('text similarity search'));
+SELECT bigm_similarity(to_bigm(''), to_bigm('wow'));
+SELECT to_bigm('pgbigm') =% to_bigm('pgbigmr'), to_bigm('pgbigm') =% to_bigm('pgtrgm');
+SELECT to_bigm('wow') <-> to_bigm(' wow ');
+
+CREATE TABLE test_bigm_vector (col1 text, col2 bigm GENERATED ALWAYS AS (to_bigm(col1)) STORED);
+INSERT INTO test_bigm_vector (col1) SELECT col1 FROM test_bigm;
+-- binary copy goes through bigm_send and bigm_recv; \copy writes the file
+-- on the client side, under the results directory of pg_regress
+CREATE TABLE test_bigm_vector_copy (col2 bigm);
+\copy (SELECT col2 FROM test_bigm_vector) TO 'test/results/test_bigm_vector.bin' WITH (FORMAT binary)
+\copy test_bigm_vector_copy FROM 'test/results/test_bigm_vector.bin' WITH (FORMAT binary)
+SELECT count(*) FROM test_bigm_vector v JOIN test_bigm_vector_copy c ON bigm_send(v.col2) = bigm_send(c.col2);
+DROP TABLE test_bigm_vector_copy;
+
+CREATE INDEX test_bigm_vector_idx ON test_bigm_vector USING gin (col2);
+EXPLAIN (COSTS off) SELECT col1 FROM test_bigm_vector WHERE col2 =% to_bigm('performance');
+SELECT count(*), min(bigm_similarity(col1, 'performance')) FROM test_bigm_vector WHERE col2 =% to_bigm('performance');
+SELECT count(*) FROM test_bigm WHERE col1 =% 'performance';
+SELECT col1 FROM test_bigm_vector WHERE col2 =% to_bigm('Tool') ORDER BY col2 <-> to_bigm('Tool'), col1 LIMIT 3;
+
+-- ANALYZE collects the most common bigrams of bigm columns and expressions
+CREATE INDEX test_bigm_vector_expr_idx ON test_bigm_vector USING gin (to_bigm(col1));
+SELECT estimated_rows($$SELECT * FROM test_bigm_vector WHERE col2 =% to_bigm('performance')$$);
+ANALYZE test_bigm_vector;
+SELECT tablename, attname, bigram, frequency FROM pg_bigm_stats
+  WHERE tablename = 'test_bigm_vector' ORDER BY attname, frequency DESC, bigram LIMIT 6;
+SELECT tablename, attname, indexname, count(*) FROM pg_bigm_stats
+  WHERE tablename = 'test_bigm_vector' GROUP BY 1, 2, 3 ORDER BY 1, 2;
+-- a text column indexed by gin_bigm_ops has the standard statistics only
+SELECT * FROM pg_bigm_stats WHERE tablename = 'test_bigm';
+SELECT estimated_rows($$SELECT * FROM test_bigm_vector WHERE col2 =% to_bigm('performance')$$),
+       count(*) FROM test_bigm_vector WHERE col2 =% to_bigm('performance');
+SELECT estimated_rows($$SELECT * FROM test_bigm_vector WHERE col2 =% to_bigm('pg_bigm is a module')$$),
+       count(*) FROM test_bigm_vector WHERE col2 =% to_bigm('pg_bigm is a module');
+BEGIN;
+SET LOCAL pg_bigmr.similarity_limit = 0.5;
+SELECT estimated_rows($$SELECT * FROM test_bigm_vector WHERE col2 =% to_bigm('pg_bigm is a module')$$),
+       count(*) FROM test_bigm_vector WHERE col2 =% to_bigm('pg_bigm is a module');
+COMMIT;
+DROP TABLE test_bigm_vector;
+DROP FUNCTION estimated_rows(text);
+
+-- tests for gist_bigm_ops
+CREATE TABLE test_bigm_gist AS SELECT * FROM test_bigm;
+CREATE INDEX test_bigm_gist_idx ON test_bigm_gist USING gist (col1 gist_bigm_ops);
+
+EXPLAIN (COSTS off) SELECT * FROM test_bigm_gist WHERE col1 LIKE likequery('bigm');
+EXPLAIN (COSTS off) SELECT * FROM test_bigm_gist WHERE col1 =% 'bigm';
+
+SELECT col1 FROM test_bigm_gist WHERE col1 LIKE likequery('Y') ORDER BY col1;
+SELECT col1 FROM test_bigm_gist WHERE col1 LIKE likequery('pi') ORDER BY col1;
+SELECT col1 FROM test_bigm_gist WHERE col1 LIKE likequery('GIN') ORDER BY col1;
+SELECT col1 FROM test_bigm_gist WHERE col1 LIKE likequery('performance') ORDER BY col1;
+SET pg_bigmr.enable_recheck = off;
+SELECT col1 FROM test_bigm_gist WHERE col1 LIKE likequery('pi') ORDER BY col1;
+SELECT col1 FROM test_bigm_gist WHERE col1 LIKE likequery('trial') ORDER BY col1;
+SET pg_bigmr.enable_recheck = on;
+
+SELECT count(*), min(bigm_similarity(col1, 'Tool')) FROM test_bigm_gist WHERE col1 =% 'Tool';
+SELECT count(*), max(bigm_similarity(col1, 'Tool')) FROM test_bigm_gist WHERE NOT col1 =% 'Tool';
+SELECT count(*), min(bigm_similarity(col1, 'performance')) FROM test_bigm_gist WHERE col1 =% 'performance';
+SELECT count(*), max(bigm_similarity(col1, 'performance')) FROM test_bigm_gist WHERE NOT col1 =% 'performance';
+
+SELECT count(*) FROM test_bigm_gist WHERE col1 LIKE likequery('she tore');
+
+-- the query cached by the index scan changes on each rescan
+SELECT q, (SELECT count(*) FROM test_bigm_gist WHERE col1 LIKE likequery(q)),
+       (SELECT count(*) FROM test_bigm_gist WHERE col1 =% q)
+FROM (VALUES ('pi'), ('Tool'), ('performance'), ('pi')) AS v(q);
+
+-- tests for bigm distance
+SELECT 'wow' <-> 'WOWa ';
+SELECT 'wow' <-> ' wow ';
+SELECT 'wow' <-> '';
+EXPLAIN (COSTS off) SELECT col1 FROM test_bigm_gist ORDER BY col1 <-> 'full text search' LIMIT 5;
+SELECT col1, col1 <-> 'full text search' AS dist FROM test_bigm_gist ORDER BY col1 <-> 'full text search' LIMIT 5;
+SELECT col1, col1 <-> 'Writes statistics' AS dist FROM test_bigm_gist ORDER BY col1 <-> 'Writes statistics' LIMIT 3;
+
+INSERT INTO test_bigm_gist SELECT * FROM test_bigm;
+INSERT INTO test_bigm_gist SELECT * FROM test_bigm;
+SELECT count(*) FROM test_bigm_gist WHERE col1 LIKE likequery('performance');
+
+-- the bigrams of long texts are stored as a signature, which is always rechecked
+INSERT INTO test_bigm_gist (col1)
+  SELECT string_agg(chr(19968 + (i * 7 + n) % 1200), '') || 'tail ' || n
+  FROM generate_series(1, 1200) i, generate_series(1, 3) n GROUP BY n;
+SET pg_bigmr.enable_recheck = off;
+SELECT right(col1, 6) FROM test_bigm_gist WHERE col1 LIKE likequery('tail 2');
+SELECT right(col1, 6) FROM test_bigm_gist WHERE col1 LIKE likequery(chr(19975) || chr(19982)) ORDER BY 1;
+SET pg_bigmr.enable_recheck = on;
+SELECT count(*) FROM test_bigm_gist WHERE col1 =% 'tail 3';
+SELECT right(col1, 6), round((col1 <-> 'tail 1')::numeric, 4) FROM test_bigm_gist
+  WHERE length(col1) > 1000 ORDER BY col1 <-> 'tail 1', col1 LIMIT 2;
+DROP TABLE test_bigm_gist;
+
 -- tests for drop of pg_bigmr
 DROP EXTENSION pg_bigmr CASCADE;
 SELECT likequery('test');
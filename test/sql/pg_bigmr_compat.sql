@@ -0,0 +1,43 @@
+-- run with shared_preload_libraries = 'pg_bigmr' and
+-- pg_bigmr.pg_bigm_compatibility = on, see installcheck
+CREATE EXTENSION pg_bigmr;
+
+SHOW pg_bigmr.pg_bigm_compatibility;
+SET pg_bigmr.pg_bigm_compatibility = off;
+
+-- tests for the pg_bigm.* aliases
+SELECT name, setting, context FROM pg_settings WHERE name LIKE 'pg\_bigm.%' ORDER BY name;
+SHOW pg_bigm.last_update;
+SET pg_bigm.last_update = '2013.09.18';
+
+-- the value of pg_bigmr.similarity_limit in postgresql.conf is kept
+SHOW pg_bigmr.similarity_limit;
+SHOW pg_bigm.similarity_limit;
+
+SET pg_bigm.similarity_limit = 0.5;
+SHOW pg_bigmr.similarity_limit;
+SELECT 'pg_bigm' =% 'pg_bigmr';
+SET pg_bigm.similarity_limit = 0.9;
+SELECT 'pg_bigm' =% 'pg_bigmr';
+RESET pg_bigm.similarity_limit;
+SHOW pg_bigmr.similarity_limit;
+SET pg_bigmr.similarity_limit = 0.6;
+SHOW pg_bigm.similarity_limit;
+SELECT 'pg_bigm' =% 'pg_bigmr';
+RESET pg_bigmr.similarity_limit;
+SHOW pg_bigm.similarity_limit;
+
+SET pg_bigm.gin_key_limit = 3;
+SHOW pg_bigmr.gin_key_limit;
+SET pg_bigmr.gin_key_limit = 5;
+SHOW pg_bigm.gin_key_limit;
+SET pg_bigm.enable_recheck = off;
+SHOW pg_bigmr.enable_recheck;
+RESET pg_bigm.enable_recheck;
+SHOW pg_bigmr.enable_recheck;
+
+-- pg_bigmr.flush_databases is only defined by shared_preload_libraries
+SHOW pg_bigmr.flush_databases;
+SET pg_bigmr.flush_databases = 'postgres';
+
+DROP EXTENSION pg_bigmr;
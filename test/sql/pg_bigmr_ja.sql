@@ -73,3 +73,80 @@ SELECT count(*), min(bigm_similarity(col1, 'インデックスを作成')) FROM
 SELECT count(*), max(bigm_similarity(col1, 'インデックスを作成')) FROM test_bigm WHERE NOT col1 =% 'インデックスを作成';
 SELECT count(*), min(bigm_similarity(col1, '3-gramの全文検索')) FROM test_bigm WHERE col1 =% '3-gramの全文検索';
 SELECT count(*), max(bigm_similarity(col1, '3-gramの全文検索')) FROM test_bigm WHERE NOT col1 =% '3-gramの全文検索';
+
+-- tests for normalization
+SELECT bigm_normalize('ＰｏｓｔｇｒｅＳＱＬ　ｶﾞｲﾄﾞ', 'width');
+SELECT bigm_normalize('ＰｏｓｔｇｒｅＳＱＬ　ｶﾞｲﾄﾞ', 'nfkc,casefold');
+SELECT bigm_normalize('ＰｏｓｔｇｒｅＳＱＬ　ｶﾞｲﾄﾞ', 'none');
+SELECT show_bigm('ＡＢ　ｶﾞｲﾄﾞ', 'width,casefold');
+SELECT bigm_normalize('ＡＢ', 'nfkd');
+
+CREATE TABLE test_bigm_normalize (col1 text);
+INSERT INTO test_bigm_normalize VALUES
+    ('ＰｏｓｔｇｒｅＳＱＬの全文検索'), ('postgresqlの全文検索'),
+    ('PostgreSQLのｲﾝﾃﾞｯｸｽ'), ('MySQLのインデックス'), ('ＡＢＣ');
+CREATE INDEX test_bigm_normalize_idx ON test_bigm_normalize USING gin (col1 gin_bigm_ops(normalize = 'nfkd'));
+CREATE INDEX test_bigm_normalize_idx ON test_bigm_normalize USING gin (col1 gin_bigm_ops(normalize = 'nfkc,casefold'));
+EXPLAIN (COSTS off) SELECT col1 FROM test_bigm_normalize WHERE col1 LIKE likequery('PostgreSQL');
+SELECT col1 FROM test_bigm_normalize WHERE col1 LIKE likequery('PostgreSQL') ORDER BY col1;
+-- the normalized keys find the normalized texts unless the rows are rechecked
+SET pg_bigmr.enable_recheck = off;
+SELECT col1 FROM test_bigm_normalize WHERE col1 LIKE likequery('PostgreSQL') ORDER BY col1;
+SELECT col1 FROM test_bigm_normalize WHERE col1 LIKE likequery('abc') ORDER BY col1;
+SELECT col1 FROM test_bigm_normalize WHERE col1 LIKE likequery('インデックス') ORDER BY col1;
+-- the similarity is defined on the texts as they are, so the rows are rechecked
+SELECT col1 FROM test_bigm_normalize WHERE col1 =% 'ＰＯＳＴＧＲＥＳＱＬの全文検索' ORDER BY col1;
+SET pg_bigmr.enable_recheck = on;
+-- the characters next to wildcards may be composed with the ones in the value
+INSERT INTO test_bigm_normalize VALUES ('ｱｶﾞ'), (E'cafe\u0301');
+SELECT col1 FROM test_bigm_normalize WHERE col1 LIKE '%ｱｶ%' ORDER BY col1;
+SELECT col1 FROM test_bigm_normalize WHERE col1 LIKE '%ﾞ' ORDER BY col1;
+SELECT col1 FROM test_bigm_normalize WHERE col1 LIKE '%cafe%' ORDER BY col1;
+SELECT col1 FROM test_bigm_normalize WHERE col1 LIKE '%ＡＢ%' ORDER BY col1;
+SELECT col1 FROM test_bigm_normalize WHERE col1 LIKE '%abc%' ORDER BY col1;
+
+-- normalized search with an expression index
+CREATE INDEX test_bigm_normalize_expr_idx ON test_bigm_normalize USING gin (bigm_normalize(col1, 'nfkc,casefold') gin_bigm_ops);
+EXPLAIN (COSTS off) SELECT col1 FROM test_bigm_normalize WHERE bigm_normalize(col1, 'nfkc,casefold') LIKE likequery(bigm_normalize('ＰｏｓｔｇｒｅＳＱＬ', 'nfkc,casefold'));
+SELECT col1 FROM test_bigm_normalize WHERE bigm_normalize(col1, 'nfkc,casefold') LIKE likequery(bigm_normalize('ＰｏｓｔｇｒｅＳＱＬ', 'nfkc,casefold')) ORDER BY col1;
+SELECT col1 FROM test_bigm_normalize WHERE bigm_normalize(col1, 'nfkc,casefold') LIKE likequery(bigm_normalize('abc', 'nfkc,casefold')) ORDER BY col1;
+DROP TABLE test_bigm_normalize;
+
+-- tests for kana folding
+SELECT bigm_fold_kana('ケンサク・サーバーのキャッシュ');
+SELECT bigm_fold_kana('ケンサク・サーバーのキャッシュ', true);
+SELECT bigm_fold_kana('ケンサク・サーバーのキャッシュ', true, true);
+SELECT bigm_fold_kana(NULL);
+SELECT show_bigm('ｹﾝｻｸ', 'width,kana');
+
+CREATE TABLE test_bigm_kana (col1 text);
+INSERT INTO test_bigm_kana VALUES ('けんさく'), ('ケンサク'), ('ｹﾝｻｸ'), ('サーバのキャッシュ'), ('さばのきやつしゆ');
+CREATE INDEX test_bigm_kana_idx ON test_bigm_kana USING gin (col1 gin_bigm_ops(kana = true));
+SET pg_bigmr.enable_recheck = off;
+SELECT col1 FROM test_bigm_kana WHERE col1 LIKE likequery('ケンサク') ORDER BY col1;
+DROP INDEX test_bigm_kana_idx;
+-- half-width katakana are folded by the width step of the normalization first
+CREATE INDEX test_bigm_kana_idx ON test_bigm_kana USING gin (col1 gin_bigm_ops(normalize = 'width', kana = true));
+SELECT col1 FROM test_bigm_kana WHERE col1 LIKE likequery('けんさく') ORDER BY col1;
+SET pg_bigmr.enable_recheck = on;
+SELECT col1 FROM test_bigm_kana WHERE col1 LIKE likequery('けんさく') ORDER BY col1;
+DROP INDEX test_bigm_kana_idx;
+CREATE INDEX test_bigm_kana_expr_idx ON test_bigm_kana USING gin (bigm_normalize(col1, 'width,kana,small_kana,long_vowel') gin_bigm_ops);
+SELECT col1 FROM test_bigm_kana WHERE bigm_normalize(col1, 'width,kana,small_kana,long_vowel') LIKE likequery(bigm_normalize('サーバーのキャッシュ', 'width,kana,small_kana,long_vowel')) ORDER BY col1;
+CREATE INDEX test_bigm_kana_fold_idx ON test_bigm_kana USING gin (bigm_fold_kana(col1) gin_bigm_ops);
+EXPLAIN (COSTS off) SELECT col1 FROM test_bigm_kana WHERE bigm_fold_kana(col1) LIKE likequery(bigm_fold_kana('ケンサク'));
+SELECT col1 FROM test_bigm_kana WHERE bigm_fold_kana(col1) LIKE likequery(bigm_fold_kana('ケンサク')) ORDER BY col1;
+DROP TABLE test_bigm_kana;
+
+-- tests for mixed-script n-grams
+CREATE TABLE test_bigm_mixed (col1 text);
+INSERT INTO test_bigm_mixed VALUES
+    ('PostgreSQLの全文検索'), ('Postgresの検索'), ('MySQLの全文検索'), ('全文検索エンジン');
+CREATE INDEX test_bigm_mixed_idx ON test_bigm_mixed USING gin (col1 gin_bigm_ops(mixed_script = true));
+SET pg_bigmr.enable_recheck = off;
+SELECT col1 FROM test_bigm_mixed WHERE col1 LIKE likequery('全文検索') ORDER BY col1;
+SELECT col1 FROM test_bigm_mixed WHERE col1 LIKE likequery('SQLの全文') ORDER BY col1;
+SET pg_bigmr.enable_recheck = on;
+SELECT col1 FROM test_bigm_mixed WHERE col1 LIKE likequery('Postgres') ORDER BY col1;
+SELECT col1 FROM test_bigm_mixed WHERE col1 LIKE likequery('L') ORDER BY col1;
+DROP TABLE test_bigm_mixed;